@@ -0,0 +1,53 @@
+//! Compares the bytecode machine with the tree-walking interpreter on the
+//! example programs, run with `cargo bench`
+
+use std::time::{Duration, Instant};
+
+use logo_interpreter::*;
+
+const PROGRAMS: [(&str, &str); 3] = [
+    ("square", include_str!("../examples/square.logo")),
+    ("tree", include_str!("../examples/tree.logo")),
+    ("fern", include_str!("../examples/fern.logo")),
+];
+
+/// Batches run with each engine, taking turns so that a busy moment of the
+/// machine slows both
+const BATCHES: usize = 100;
+
+/// Average time of a run over a batch lasting at least a hundredth of a second
+fn batch(input: &str, engine: Engine) -> Duration {
+    let options = Options {
+        engine,
+        ..Options::default()
+    };
+    let mut runs = 0;
+    let started = Instant::now();
+    while runs < 1 || started.elapsed() < Duration::from_millis(10) {
+        execute(input, &options).unwrap();
+        runs += 1;
+    }
+    started.elapsed() / runs
+}
+
+fn main() {
+    println!(
+        "{:<8} {:>12} {:>12} {:>8}",
+        "program", "tree walk", "bytecode", "speedup"
+    );
+    for (name, input) in PROGRAMS {
+        // The fastest batch of each engine, the others having been slowed down
+        let (mut tree_walk, mut bytecode) = (Duration::MAX, Duration::MAX);
+        for _ in 0..BATCHES {
+            tree_walk = tree_walk.min(batch(input, Engine::TreeWalk));
+            bytecode = bytecode.min(batch(input, Engine::Bytecode));
+        }
+        println!(
+            "{:<8} {:>12.2?} {:>12.2?} {:>7.1}x",
+            name,
+            tree_walk,
+            bytecode,
+            tree_walk.as_secs_f64() / bytecode.as_secs_f64()
+        );
+    }
+}
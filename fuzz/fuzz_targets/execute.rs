@@ -0,0 +1,28 @@
+//! Runs arbitrary text with both engines under limits, which must stop every
+//! program with a result rather than a panic or a hang
+#![no_main]
+
+use std::time::Duration;
+
+use libfuzzer_sys::fuzz_target;
+use logo_interpreter::{record, render, Engine, Limits, Options};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(input) = std::str::from_utf8(data) else {
+        return;
+    };
+    for engine in [Engine::Bytecode, Engine::TreeWalk] {
+        let options = Options {
+            engine,
+            limits: Limits {
+                instructions: Some(10_000),
+                depth: Some(100),
+                segments: Some(10_000),
+                time: Some(Duration::from_secs(1)),
+            },
+            ..Options::default()
+        };
+        let _ = render(input, &options);
+        let _ = record(input, &options);
+    }
+});
@@ -0,0 +1,14 @@
+//! Parses, checks and formats arbitrary text, which must give errors rather
+//! than panic
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use logo_interpreter::{check, format, parse};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(input) = std::str::from_utf8(data) {
+        let _ = parse(input);
+        let _ = check(input);
+        let _ = format(input);
+    }
+});
@@ -0,0 +1,271 @@
+//! Language server for Logo programs, talking to the editor over stdio
+use std::collections::HashMap;
+use std::error::Error;
+
+use logo_interpreter::{check, parse_all, signature, Functions, Severity, Span, PRIMITIVES};
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument,
+    Notification as NotificationType, PublishDiagnostics,
+};
+use lsp_types::request::{
+    Completion, DocumentSymbolRequest, GotoDefinition, HoverRequest, Request as RequestType,
+};
+use lsp_types::*;
+use serde_json::{from_value, json};
+
+/// Position of a byte of the text, counted in lines and UTF-16 units as
+/// editors do
+fn position(text: &str, offset: usize) -> Position {
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+    let line = before.matches('\n').count();
+    Position::new(
+        line as u32,
+        before[line_start..].encode_utf16().count() as u32,
+    )
+}
+
+fn range(text: &str, span: Span) -> Range {
+    Range::new(position(text, span.start), position(text, span.end))
+}
+
+/// Byte of the text at an editor position
+fn offset(text: &str, position: Position) -> usize {
+    let mut line_start = 0;
+    for _ in 0..position.line {
+        match text[line_start..].find('\n') {
+            Some(newline) => line_start += newline + 1,
+            None => return text.len(),
+        }
+    }
+    let mut units = 0;
+    for (i, c) in text[line_start..].char_indices() {
+        if units >= position.character as usize || c == '\n' {
+            return line_start + i;
+        }
+        units += c.len_utf16();
+    }
+    text.len()
+}
+
+/// Word of the text around a byte
+fn word_at(text: &str, offset: usize) -> &str {
+    let in_word = |c: char| !c.is_whitespace() && !"[]()*/;".contains(c);
+    let start = text[..offset]
+        .char_indices()
+        .rev()
+        .find(|&(_, c)| !in_word(c))
+        .map_or(0, |(i, c)| i + c.len_utf8());
+    let end = text[offset..]
+        .find(|c: char| !in_word(c))
+        .map_or(text.len(), |i| offset + i);
+    &text[start..end]
+}
+
+/// Span of the name in a definition
+fn name_span(text: &str, label: &str, header: Span) -> Span {
+    // The name is the first word after `to`
+    let start = header.start + text[header.start + 2..].find(label).map_or(0, |i| i + 2);
+    Span {
+        start,
+        end: start + label.len(),
+    }
+}
+
+#[derive(Default)]
+struct Server {
+    documents: HashMap<Uri, String>,
+}
+
+impl Server {
+    fn run(&mut self, connection: &Connection) -> Result<(), Box<dyn Error + Send + Sync>> {
+        for message in &connection.receiver {
+            match message {
+                Message::Request(request) => {
+                    if connection.handle_shutdown(&request)? {
+                        return Ok(());
+                    }
+                    connection
+                        .sender
+                        .send(Message::Response(self.respond(request)))?;
+                }
+                Message::Notification(notification) => {
+                    if let Some(diagnostics) = self.update(notification) {
+                        connection.sender.send(Message::Notification(diagnostics))?;
+                    }
+                }
+                Message::Response(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Keeps track of the open documents, giving their new diagnostics
+    fn update(&mut self, notification: Notification) -> Option<Notification> {
+        let (uri, text) = match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: DidOpenTextDocumentParams = from_value(notification.params).ok()?;
+                (params.text_document.uri, params.text_document.text)
+            }
+            DidChangeTextDocument::METHOD => {
+                // Documents are synchronised in full, the last change holds the whole text
+                let params: DidChangeTextDocumentParams = from_value(notification.params).ok()?;
+                let change = params.content_changes.into_iter().last()?;
+                (params.text_document.uri, change.text)
+            }
+            DidCloseTextDocument::METHOD => {
+                let params: DidCloseTextDocumentParams = from_value(notification.params).ok()?;
+                self.documents.remove(&params.text_document.uri);
+                return Some(publish(params.text_document.uri, vec![]));
+            }
+            _ => return None,
+        };
+
+        let diagnostics = check(&text)
+            .into_iter()
+            .map(|diagnostic| Diagnostic {
+                range: range(&text, diagnostic.span),
+                severity: Some(match diagnostic.severity {
+                    Severity::Error => DiagnosticSeverity::ERROR,
+                    Severity::Warning => DiagnosticSeverity::WARNING,
+                }),
+                source: Some(String::from("logo")),
+                message: diagnostic.message,
+                ..Diagnostic::default()
+            })
+            .collect();
+        self.documents.insert(uri.clone(), text);
+        Some(publish(uri, diagnostics))
+    }
+
+    fn respond(&self, request: Request) -> Response {
+        let result = match request.method.as_str() {
+            GotoDefinition::METHOD => from_value(request.params)
+                .ok()
+                .map(|params| json!(self.definition(params))),
+            HoverRequest::METHOD => from_value(request.params)
+                .ok()
+                .map(|params| json!(self.hover(params))),
+            Completion::METHOD => from_value(request.params)
+                .ok()
+                .map(|params| json!(self.completion(params))),
+            DocumentSymbolRequest::METHOD => from_value(request.params)
+                .ok()
+                .map(|params| json!(self.symbols(params))),
+            method => {
+                let message = format!("Unsupported request {}", method);
+                return Response::new_err(request.id, ErrorCode::MethodNotFound as i32, message);
+            }
+        };
+        match result {
+            Some(result) => Response::new_ok(request.id, result),
+            None => {
+                let message = String::from("Invalid parameters");
+                Response::new_err(request.id, ErrorCode::InvalidParams as i32, message)
+            }
+        }
+    }
+
+    /// Text of a document and the procedures it defines
+    fn document(&self, uri: &Uri) -> Option<(&str, Functions)> {
+        let text = self.documents.get(uri)?;
+        let (_, fns, _) = parse_all(text);
+        Some((text, fns))
+    }
+
+    /// Procedure named by the word under the cursor
+    fn procedure(&self, at: &TextDocumentPositionParams) -> Option<(&str, Functions, String)> {
+        let (text, fns) = self.document(&at.text_document.uri)?;
+        let label = word_at(text, offset(text, at.position)).to_string();
+        fns.contains(&label).then_some((text, fns, label))
+    }
+
+    fn definition(&self, params: GotoDefinitionParams) -> Option<GotoDefinitionResponse> {
+        let at = params.text_document_position_params;
+        let (text, fns, label) = self.procedure(&at)?;
+        let fun = fns.get(&label)?;
+        let location = Location::new(at.text_document.uri, range(text, fun.span));
+        Some(GotoDefinitionResponse::Scalar(location))
+    }
+
+    fn hover(&self, params: HoverParams) -> Option<Hover> {
+        let (_, fns, label) = self.procedure(&params.text_document_position_params)?;
+        let signature = signature(&label, fns.get(&label)?);
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: format!("```logo\n{}\n```", signature),
+            }),
+            range: None,
+        })
+    }
+
+    fn completion(&self, params: CompletionParams) -> Option<CompletionResponse> {
+        let primitives = PRIMITIVES
+            .iter()
+            .filter(|primitive| primitive.starts_with(char::is_alphabetic))
+            .map(|primitive| CompletionItem {
+                label: primitive.to_string(),
+                kind: Some(CompletionItemKind::KEYWORD),
+                ..CompletionItem::default()
+            });
+        let mut items: Vec<_> = primitives.collect();
+        if let Some((_, fns)) = self.document(&params.text_document_position.text_document.uri) {
+            let mut procedures: Vec<_> = fns.functions.iter().collect();
+            procedures.sort_by_key(|(label, _)| label.as_str());
+            items.extend(procedures.into_iter().map(|(label, fun)| CompletionItem {
+                label: label.clone(),
+                kind: Some(CompletionItemKind::FUNCTION),
+                detail: Some(signature(label, fun)),
+                ..CompletionItem::default()
+            }));
+        }
+        Some(CompletionResponse::Array(items))
+    }
+
+    fn symbols(&self, params: DocumentSymbolParams) -> Option<DocumentSymbolResponse> {
+        let (text, fns) = self.document(&params.text_document.uri)?;
+        let mut procedures: Vec<_> = fns.functions.iter().collect();
+        procedures.sort_by_key(|(_, fun)| fun.span.start);
+        let symbols = procedures
+            .into_iter()
+            .map(|(label, fun)| {
+                #[allow(deprecated)]
+                DocumentSymbol {
+                    name: label.clone(),
+                    detail: Some(signature(label, fun)),
+                    kind: SymbolKind::FUNCTION,
+                    tags: None,
+                    deprecated: None,
+                    range: range(text, fun.span.to(fun.end)),
+                    selection_range: range(text, name_span(text, label, fun.span)),
+                    children: None,
+                }
+            })
+            .collect();
+        Some(DocumentSymbolResponse::Nested(symbols))
+    }
+}
+
+fn publish(uri: Uri, diagnostics: Vec<Diagnostic>) -> Notification {
+    let params = PublishDiagnosticsParams::new(uri, diagnostics, None);
+    Notification::new(PublishDiagnostics::METHOD.to_string(), params)
+}
+
+fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
+    let (connection, io_threads) = Connection::stdio();
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        definition_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        completion_provider: Some(CompletionOptions::default()),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        ..ServerCapabilities::default()
+    };
+    connection.initialize(serde_json::to_value(capabilities)?)?;
+    Server::default().run(&connection)?;
+    drop(connection);
+    io_threads.join()?;
+    Ok(())
+}
@@ -1,18 +1,81 @@
+mod bytecode;
+mod check;
+mod debugger;
+mod error;
+mod format;
 mod parser;
+mod profile;
+mod recording;
+mod trace;
 mod turtle;
+mod turtles;
+mod vm;
+mod walker;
+mod font;
 mod image;
+mod lexer;
+mod limits;
 mod parser_types;
+mod raster;
 
-use turtle::*;
-use parser::*;
+use bytecode::*;
+use vm::*;
+use walker::*;
 use image::*;
 use parser_types::*;
+use trace::*;
 
-use std::collections::HashMap;
 
-fn substitute_token(token: &Token, param_evaluator: &HashMap<String, f64>) -> Token {
+pub use check::{check, Diagnostic, Severity};
+pub use debugger::{Breakpoint, CallFrame, Debugger, Pause};
+pub use error::{LogoError, ParseError};
+pub use format::{format, signature};
+pub use image::Animation;
+pub use lexer::Span;
+pub use limits::{Limit, Limits};
+pub use parser::{parse, parse_all, PRIMITIVES};
+pub use profile::{Counts, Profile};
+pub use recording::{Curve, Fill, Recording, Segment, Style, Text};
+pub use parser_types::{Arg, Color, Expr, Fun, Functions, List, LogExpr, Token, Word};
+pub use turtle::TurtleState;
+
+fn substitute_token(token: &Token, param_evaluator: &Bindings) -> Token {
     match token {
-        Token::Stop => {Token::Stop},
+        Token::Stop
+        | Token::Wrap
+        | Token::Window
+        | Token::Fence
+        | Token::ShowTurtle
+        | Token::HideTurtle
+        | Token::PenUp
+        | Token::PenDown
+        | Token::NewTurtle
+        | Token::Fill
+        | Token::Comment(..)
+        | Token::Source(_) => token.clone(),
+        Token::SetPenColor(color) => Token::SetPenColor(color.substitute(param_evaluator)),
+        Token::SetPenSize(expr) => Token::SetPenSize(substitute_expr(expr, param_evaluator)),
+        Token::Tell(ids) => Token::Tell(
+            ids.iter()
+                .map(|id| *substitute_expr(id, param_evaluator))
+                .collect(),
+        ),
+        Token::Ask(ids, body) => Token::Ask(
+            ids.iter()
+                .map(|id| *substitute_expr(id, param_evaluator))
+                .collect(),
+            Box::new(substitute_token(body, param_evaluator)),
+        ),
+        Token::SetFillColor(color) => Token::SetFillColor(color.substitute(param_evaluator)),
+        Token::Label(word) => Token::Label(word.substitute(param_evaluator)),
+        Token::SetLabelHeight(expr) => {
+            Token::SetLabelHeight(substitute_expr(expr, param_evaluator))
+        }
+        Token::SetLabelFont(word) => Token::SetLabelFont(word.substitute(param_evaluator)),
+        Token::Filled(color, body) => Token::Filled(
+            color.substitute(param_evaluator),
+            Box::new(substitute_token(body, param_evaluator)),
+        ),
         Token::Forward(expr) => {
             Token::Forward(substitute_expr(expr, param_evaluator))
         }
@@ -25,6 +88,15 @@ fn substitute_token(token: &Token, param_evaluator: &HashMap<String, f64>) -> To
         Token::TurnLeft(expr) => {
             Token::TurnLeft(substitute_expr(expr, param_evaluator))
         }
+        Token::Arc(angle, radius) => Token::Arc(
+            substitute_expr(angle, param_evaluator),
+            substitute_expr(radius, param_evaluator),
+        ),
+        Token::Circle(radius) => Token::Circle(substitute_expr(radius, param_evaluator)),
+        Token::Ellipse(width, height) => Token::Ellipse(
+            substitute_expr(width, param_evaluator),
+            substitute_expr(height, param_evaluator),
+        ),
         Token::Repeat(expr, body) => {
             let substituted_expr = substitute_expr(expr, param_evaluator);
             let substituted_body = substitute_token(body, param_evaluator);
@@ -43,24 +115,27 @@ fn substitute_token(token: &Token, param_evaluator: &HashMap<String, f64>) -> To
         Token::FnCall(label, args) => {
             Token::FnCall(label.clone(), args.iter().map(|arg| *substitute_expr(arg, param_evaluator)).collect())
         }
-        Token::FnLabel(label) =>
-        {token.clone()}
         Token::If(log_expr, block) => {
             Token::If(log_expr.substitute(param_evaluator), Box::new(substitute_token(block, param_evaluator)))
         }
     }
 }
 
-fn substitute_expr(expr: &Expr, param_evaluator: &HashMap<String, f64>) -> Box<Expr> {
+fn substitute_expr(expr: &Expr, param_evaluator: &Bindings) -> Box<Expr> {
     match expr {
         Expr::Arg(Arg::Param(param)) => {
-            if let Some(&value) = param_evaluator.get(param) {
+            if let Some(&value) = param_evaluator.numbers.get(param) {
                 Box::new(Expr::Arg(Arg::Val(value)))
             } else {
                 panic!("Parameter '{}' not found in evaluator", param);
             }
         }
-        Expr::Arg(Arg::Val(_)) => {Box::new(expr.clone())},
+        Expr::Arg(Arg::Val(_)) | Expr::Arg(Arg::Who) => {Box::new(expr.clone())},
+        Expr::Count(list) => Box::new(Expr::Count(substitute_list(list, param_evaluator))),
+        Expr::Item(index, list) => Box::new(Expr::Item(
+            substitute_expr(index, param_evaluator),
+            substitute_list(list, param_evaluator),
+        )),
         Expr::Mul(lhs, rhs) => Box::new(Expr::Mul(
             substitute_expr(lhs, param_evaluator),
             substitute_expr(rhs, param_evaluator),
@@ -81,16 +156,172 @@ fn substitute_expr(expr: &Expr, param_evaluator: &HashMap<String, f64>) -> Box<E
 }
 
 
-pub fn run(input: &str, image_path: &str) {
-    let (commands, fns) = parse(input);
+fn substitute_list(list: &List, param_evaluator: &Bindings) -> List {
+    match list {
+        List::Param(param) => match param_evaluator.lists.get(param) {
+            Some(values) => List::Values(values.clone()),
+            None => panic!("Parameter '{}' not found in evaluator", param),
+        },
+        List::Values(_) => list.clone(),
+    }
+}
 
-    let mut turtle = Turtle::new();
-    let mut image = Image::new();
+/// How the parsed program gets executed
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Engine {
+    /// Compiled to bytecode run by a stack machine
+    #[default]
+    Bytecode,
+    /// Reference interpreter walking the parsed tokens
+    TreeWalk,
+}
+
+pub struct Options {
+    /// Number of digits after the decimal point written for SVG coordinates
+    pub precision: usize,
+    /// Replay the drawing over time instead of saving a still image
+    pub animation: Option<Animation>,
+    pub limits: Limits,
+    pub engine: Engine,
+    /// File receiving a JSON line for every turtle command executed
+    pub trace: Option<String>,
+    /// Tag the SVG elements with the instruction and procedure calls that
+    /// drew them, listed in a JSON file next to the image
+    pub tag_origins: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            precision: 2,
+            animation: None,
+            limits: Limits::default(),
+            engine: Engine::default(),
+            trace: None,
+            tag_origins: false,
+        }
+    }
+}
+
+pub fn run(input: &str, image_path: &str) -> Result<(), LogoError> {
+    run_with_options(input, image_path, &Options::default())
+}
+
+/// Runs the program and saves the drawing, which is kept up to the point of
+/// failure when execution stops with an error. A drawing that cannot be saved
+/// is reported before any error of the program.
+pub fn run_with_options(
+    input: &str,
+    image_path: &str,
+    options: &Options,
+) -> Result<(), LogoError> {
+    let drawing = draw(input, options, None);
+    drawing.image.save(image_path).and(drawing.result)
+}
+
+/// Runs the program and saves the drawing like `run_with_options`, counting
+/// the work done by each procedure and line of the program
+pub fn run_profiled(
+    input: &str,
+    image_path: &str,
+    options: &Options,
+) -> (Profile, Result<(), LogoError>) {
+    let drawing = draw(input, options, Some(Profile::new(input)));
+    let result = drawing.image.save(image_path).and(drawing.result);
+    (drawing.profile.unwrap_or_default(), result)
+}
+
+/// Runs the program and gives the drawing as SVG text instead of saving it
+pub fn render(input: &str, options: &Options) -> (String, Result<(), LogoError>) {
+    let drawing = draw(input, options, None);
+    (drawing.image.svg(), drawing.result)
+}
 
-    //println!("Begin executing commands {:?}", commands);
-    for command in commands {
-        turtle.execute(&command, &mut image, &fns);
+/// Runs the program and gives what it drew as data, with the final state of
+/// the turtles, instead of an image
+pub fn record(input: &str, options: &Options) -> (Recording, Result<(), LogoError>) {
+    let drawing = draw(input, options, None);
+    (drawing.image.record(drawing.turtles), drawing.result)
+}
+
+/// Runs the program without saving the drawing
+pub fn execute(input: &str, options: &Options) -> Result<(), LogoError> {
+    draw(input, options, None).result
+}
+
+/// Outcome of running a program
+struct Drawing {
+    image: Image,
+    result: Result<(), LogoError>,
+    profile: Option<Profile>,
+    /// Final state of every turtle
+    turtles: Vec<TurtleState>,
+}
+
+fn draw(input: &str, options: &Options, profile: Option<Profile>) -> Drawing {
+    let mut image = Image::new();
+    image.set_precision(options.precision);
+    image.set_animation(options.animation.clone());
+    image.set_limits(&options.limits);
+    if options.tag_origins {
+        image.tag_origins(input);
     }
 
-    image.save(image_path);
-}
\ No newline at end of file
+    let (commands, fns) = match parse(input) {
+        Ok(program) => program,
+        Err(error) => {
+            return Drawing {
+                image,
+                result: Err(error.into()),
+                profile,
+                turtles: vec![],
+            }
+        }
+    };
+
+    let trace = match options.trace.as_deref().map(Trace::create).transpose() {
+        Ok(trace) => trace,
+        Err(error) => {
+            return Drawing {
+                image,
+                result: Err(error),
+                profile,
+                turtles: vec![],
+            }
+        }
+    };
+
+    let (result, profile, turtles) = match options.engine {
+        Engine::Bytecode => {
+            let program = compile(&commands, &fns);
+            let mut vm = Vm::new(options.limits.clone());
+            if let Some(trace) = trace {
+                vm.set_trace(trace);
+            }
+            if let Some(profile) = profile {
+                vm.set_profile(profile);
+            }
+            let result = vm.run(&program, &mut image);
+            vm.show(&mut image);
+            (result, vm.take_profile(), vm.turtles())
+        }
+        Engine::TreeWalk => {
+            let mut walker = Walker::new(options.limits.clone());
+            if let Some(trace) = trace {
+                walker.set_trace(trace);
+            }
+            if let Some(profile) = profile {
+                walker.set_profile(profile);
+            }
+            let result = walker.execute(commands, &mut image, &fns);
+            walker.show(&mut image);
+            (result, walker.take_profile(), walker.turtles())
+        }
+    };
+    Drawing {
+        image,
+        result,
+        profile,
+        turtles,
+    }
+}
@@ -0,0 +1,444 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::interpreter::turtle::Boundary;
+use crate::interpreter::*;
+
+/// Turtle primitives, taking their inputs from the stack in source order
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Primitive {
+    Forward,
+    Back,
+    TurnRight,
+    TurnLeft,
+    Arc,
+    Circle,
+    Ellipse,
+    SetFillColor,
+    Fill,
+    Label,
+    SetLabelHeight,
+    SetLabelFont,
+    SetPenColor,
+    SetPenSize,
+    PenUp,
+    PenDown,
+    ShowTurtle,
+    HideTurtle,
+}
+
+/// Instructions of the stack machine, addresses are indices into `Program::code`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Op {
+    Number(f64),
+    Word(Rc<str>),
+    /// Input of the running procedure, by position
+    Param(usize),
+    Who,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Greater,
+    Less,
+    /// Replaces a number by the palette color it stands for
+    Palette,
+    /// Replaces a number by its text
+    Text,
+    JumpUnless(usize),
+    /// Pops the number of iterations, skipping to the address when there are none
+    Repeat(usize),
+    /// End of a `repeat` body, jumping back to the address while iterations remain
+    Next(usize),
+    /// Runs the following instructions up to `NextTurtle` once per active
+    /// turtle, skipping to the address when there are none
+    Each(usize),
+    NextTurtle(usize),
+    /// Calls a procedure by its index in `Program::procedures`, with the
+    /// number of values on the stack holding its inputs
+    Call(usize, usize),
+    /// Call in tail position, reusing the frame of the running procedure
+    TailCall(usize, usize),
+    /// Pops a position and pushes that element of the rest input stored at
+    /// the slot, its length followed by the values
+    Item(usize),
+    Return,
+    /// `stop` outside of procedures, abandoning the current top level command
+    Break(usize),
+    Tell(usize),
+    Ask(usize),
+    EndAsk,
+    Filled,
+    /// Starts recording the outline to fill with the color on the stack
+    Outline,
+    EndFilled,
+    NewTurtle,
+    Boundary(Boundary),
+    Primitive(Primitive),
+    Halt,
+}
+
+#[derive(Debug)]
+pub struct Procedure {
+    pub name: String,
+    pub entry: usize,
+    /// Names of the inputs, in the order of their stack slots
+    pub params: Vec<String>,
+    pub rest: Option<String>,
+}
+
+/// Compiled program, the top level commands start at address 0
+#[derive(Debug)]
+pub struct Program {
+    pub code: Vec<Op>,
+    pub procedures: Vec<Procedure>,
+    /// Source of the instruction each op belongs to
+    pub spans: Vec<Span>,
+    /// Whether each op is the first one of its instruction
+    pub starts: Vec<bool>,
+}
+
+/// Compiles the top level commands and the procedures they reach
+pub fn compile(commands: &[Token], fns: &Functions) -> Program {
+    let mut compiler = Compiler {
+        fns,
+        code: vec![],
+        procedures: vec![],
+        spans: vec![],
+        starts: vec![],
+        span: Span::default(),
+        start: false,
+        indices: HashMap::new(),
+        in_procedure: false,
+        params: vec![],
+        rest: None,
+        breaks: vec![],
+    };
+
+    for command in commands {
+        compiler.token(command, false);
+        let end = compiler.code.len();
+        for address in std::mem::take(&mut compiler.breaks) {
+            compiler.code[address] = Op::Break(end);
+        }
+    }
+    compiler.emit(Op::Halt);
+    compiler.in_procedure = true;
+
+    // Procedures are queued as calls to them get compiled
+    let mut next = 0;
+    while next < compiler.procedures.len() {
+        let name = compiler.procedures[next].name.clone();
+        let fun = fns.get(&name).unwrap();
+        compiler.procedures[next].entry = compiler.code.len();
+        compiler.params = fun.params.clone();
+        compiler.rest = fun.rest.clone();
+        // Returning belongs to the `end` of the definition
+        compiler.span = fun.end;
+        compiler.block(&fun.body, true);
+        compiler.emit(Op::Return);
+        next += 1;
+    }
+
+    Program {
+        code: compiler.code,
+        procedures: compiler.procedures,
+        spans: compiler.spans,
+        starts: compiler.starts,
+    }
+}
+
+/// Replaces the inputs named in an expression by the expressions giving them
+fn bind(expr: &Expr, params: &[String], values: &[Expr]) -> Expr {
+    let bind = |expr: &Expr| Box::new(bind(expr, params, values));
+    match expr {
+        Expr::Arg(Arg::Param(param)) => match params.iter().position(|p| p == param) {
+            Some(i) if i < values.len() => values[i].clone(),
+            _ => panic!("Parameter '{}' not found in evaluator", param),
+        },
+        Expr::Arg(_) | Expr::Count(_) => expr.clone(),
+        Expr::Item(index, list) => Expr::Item(bind(index), list.clone()),
+        Expr::Mul(lhs, rhs) => Expr::Mul(bind(lhs), bind(rhs)),
+        Expr::Div(lhs, rhs) => Expr::Div(bind(lhs), bind(rhs)),
+        Expr::Add(lhs, rhs) => Expr::Add(bind(lhs), bind(rhs)),
+        Expr::Sub(lhs, rhs) => Expr::Sub(bind(lhs), bind(rhs)),
+    }
+}
+
+fn uses_who(expr: &Expr) -> bool {
+    match expr {
+        Expr::Arg(Arg::Who) => true,
+        Expr::Arg(_) | Expr::Count(_) => false,
+        Expr::Item(index, _) => uses_who(index),
+        Expr::Mul(lhs, rhs) | Expr::Div(lhs, rhs) | Expr::Add(lhs, rhs) | Expr::Sub(lhs, rhs) => {
+            uses_who(lhs) || uses_who(rhs)
+        }
+    }
+}
+
+/// Inputs of a primitive token, with the operation it compiles to
+fn primitive(token: &Token) -> Option<(Primitive, Vec<Input>)> {
+    let number = |expr: &Expr| Input::Number(expr.clone());
+    let primitive = match token {
+        Token::Forward(expr) => (Primitive::Forward, vec![number(expr)]),
+        Token::Back(expr) => (Primitive::Back, vec![number(expr)]),
+        Token::TurnRight(expr) => (Primitive::TurnRight, vec![number(expr)]),
+        Token::TurnLeft(expr) => (Primitive::TurnLeft, vec![number(expr)]),
+        Token::Arc(angle, radius) => (Primitive::Arc, vec![number(angle), number(radius)]),
+        Token::Circle(radius) => (Primitive::Circle, vec![number(radius)]),
+        Token::Ellipse(width, height) => (Primitive::Ellipse, vec![number(width), number(height)]),
+        Token::SetFillColor(color) => (Primitive::SetFillColor, vec![Input::Color(color.clone())]),
+        Token::Fill => (Primitive::Fill, vec![]),
+        Token::Label(word) => (Primitive::Label, vec![Input::Word(word.clone())]),
+        Token::SetLabelHeight(expr) => (Primitive::SetLabelHeight, vec![number(expr)]),
+        Token::SetLabelFont(word) => (Primitive::SetLabelFont, vec![Input::Word(word.clone())]),
+        Token::SetPenColor(color) => (Primitive::SetPenColor, vec![Input::Color(color.clone())]),
+        Token::SetPenSize(expr) => (Primitive::SetPenSize, vec![number(expr)]),
+        Token::PenUp => (Primitive::PenUp, vec![]),
+        Token::PenDown => (Primitive::PenDown, vec![]),
+        Token::ShowTurtle => (Primitive::ShowTurtle, vec![]),
+        Token::HideTurtle => (Primitive::HideTurtle, vec![]),
+        _ => return None,
+    };
+    Some(primitive)
+}
+
+enum Input {
+    Number(Expr),
+    Color(Color),
+    Word(Word),
+}
+
+impl Input {
+    fn uses_who(&self) -> bool {
+        match self {
+            Input::Number(expr) => uses_who(expr),
+            Input::Color(Color::Index(expr)) | Input::Word(Word::Number(expr)) => uses_who(expr),
+            Input::Color(Color::Name(_)) | Input::Word(Word::Literal(_)) => false,
+        }
+    }
+}
+
+struct Compiler<'a> {
+    fns: &'a Functions,
+    code: Vec<Op>,
+    procedures: Vec<Procedure>,
+    spans: Vec<Span>,
+    starts: Vec<bool>,
+    /// Instruction being compiled, and whether it has no op yet
+    span: Span,
+    start: bool,
+    indices: HashMap<String, usize>,
+    in_procedure: bool,
+    /// Inputs of the procedure being compiled
+    params: Vec<String>,
+    rest: Option<String>,
+    /// `Break` instructions waiting for the end of the top level command
+    breaks: Vec<usize>,
+}
+
+impl Compiler<'_> {
+    fn emit(&mut self, op: Op) {
+        self.code.push(op);
+        self.spans.push(self.span);
+        self.starts.push(std::mem::take(&mut self.start));
+    }
+
+    fn procedure(&mut self, label: &str) -> usize {
+        if let Some(&index) = self.indices.get(label) {
+            return index;
+        }
+        let fun = match self.fns.get(&label.to_string()) {
+            Some(fun) => fun,
+            None => panic!("Function '{}' not found in the environment", label),
+        };
+        let index = self.procedures.len();
+        self.procedures.push(Procedure {
+            name: label.to_string(),
+            entry: 0,
+            params: fun.params.clone(),
+            rest: fun.rest.clone(),
+        });
+        self.indices.insert(label.to_string(), index);
+        index
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Arg(Arg::Val(value)) => self.emit(Op::Number(*value)),
+            Expr::Arg(Arg::Who) => self.emit(Op::Who),
+            Expr::Arg(Arg::Param(param)) => match self.params.iter().position(|p| p == param) {
+                Some(slot) => self.emit(Op::Param(slot)),
+                None => panic!("Parameter '{}' not found in evaluator", param),
+            },
+            // The rest input follows the others, starting with its length
+            Expr::Count(list) => {
+                let slot = self.list(list);
+                self.emit(Op::Param(slot));
+            }
+            Expr::Item(index, list) => {
+                self.expr(index);
+                let slot = self.list(list);
+                self.emit(Op::Item(slot));
+            }
+            Expr::Mul(lhs, rhs) => self.binary(lhs, rhs, Op::Mul),
+            Expr::Div(lhs, rhs) => self.binary(lhs, rhs, Op::Div),
+            Expr::Add(lhs, rhs) => self.binary(lhs, rhs, Op::Add),
+            Expr::Sub(lhs, rhs) => self.binary(lhs, rhs, Op::Sub),
+        }
+    }
+
+    fn list(&self, list: &List) -> usize {
+        match list {
+            List::Param(param) if self.rest.as_ref() == Some(param) => self.params.len(),
+            List::Param(param) => panic!("Parameter '{}' not found in evaluator", param),
+            List::Values(_) => unreachable!("Values are only bound by the tree walker"),
+        }
+    }
+
+    /// Pushes the inputs of a call, returning the number of stack slots they take
+    fn inputs(&mut self, fun: &Fun, args: &[Expr]) -> usize {
+        let mut values: Vec<Expr> = args.iter().take(fun.params.len()).cloned().collect();
+        for default in &fun.defaults[values.len() - fun.arity()..] {
+            // Defaults may refer to the inputs before them
+            values.push(bind(default, &fun.params, &values));
+        }
+        values.iter().for_each(|value| self.expr(value));
+        if fun.rest.is_none() {
+            return values.len();
+        }
+
+        let rest = &args[values.len().min(args.len())..];
+        self.emit(Op::Number(rest.len() as f64));
+        rest.iter().for_each(|value| self.expr(value));
+        values.len() + 1 + rest.len()
+    }
+
+    fn binary(&mut self, lhs: &Expr, rhs: &Expr, op: Op) {
+        self.expr(lhs);
+        self.expr(rhs);
+        self.emit(op);
+    }
+
+    fn input(&mut self, input: &Input) {
+        match input {
+            Input::Number(expr) => self.expr(expr),
+            Input::Color(Color::Name(name)) | Input::Word(Word::Literal(name)) => {
+                self.emit(Op::Word(Rc::from(name.as_str())))
+            }
+            Input::Color(Color::Index(expr)) => {
+                self.expr(expr);
+                self.emit(Op::Palette);
+            }
+            Input::Word(Word::Number(expr)) => {
+                self.expr(expr);
+                self.emit(Op::Text);
+            }
+        }
+    }
+
+    /// Emits `op` after its inputs, once per active turtle when they depend on `who`
+    fn each(&mut self, inputs: &[Input], op: Op) {
+        if !inputs.iter().any(Input::uses_who) {
+            inputs.iter().for_each(|input| self.input(input));
+            self.emit(op);
+            return;
+        }
+
+        let start = self.code.len();
+        self.emit(Op::Each(0));
+        inputs.iter().for_each(|input| self.input(input));
+        self.emit(op);
+        self.emit(Op::NextTurtle(start + 1));
+        self.code[start] = Op::Each(self.code.len());
+    }
+
+    fn block(&mut self, tokens: &[Token], tail: bool) {
+        // The ops closing the block belong to the instruction opening it
+        let span = self.span;
+        for (i, token) in tokens.iter().enumerate() {
+            self.token(token, tail && no_instructions(&tokens[i + 1..]));
+        }
+        self.span = span;
+        self.start = false;
+    }
+
+    fn body(&mut self, body: &Token, tail: bool, name: &str) {
+        match body {
+            Token::Bracket(tokens) => self.block(tokens, tail),
+            _ => panic!("{} body must be a Bracket token", name),
+        }
+    }
+
+    /// Compiles a token, `tail` telling whether the procedure returns right after it
+    fn token(&mut self, token: &Token, tail: bool) {
+        if let Some((primitive, inputs)) = primitive(token) {
+            self.each(&inputs, Op::Primitive(primitive));
+            return;
+        }
+
+        match token {
+            Token::Repeat(expr, body) => {
+                self.expr(expr);
+                let start = self.code.len();
+                self.emit(Op::Repeat(0));
+                self.body(body, false, "Repeat");
+                self.emit(Op::Next(start + 1));
+                self.code[start] = Op::Repeat(self.code.len());
+            }
+            Token::FnCall(label, args) => {
+                let index = self.procedure(label);
+                let fns = self.fns;
+                let fun = fns.get(label).unwrap();
+                let slots = self.inputs(fun, args);
+                self.emit(match tail {
+                    true => Op::TailCall(index, slots),
+                    false => Op::Call(index, slots),
+                });
+            }
+            Token::Bracket(tokens) => self.block(tokens, tail),
+            Token::If(log_expr, body) => {
+                match log_expr {
+                    LogExpr::Greater(lhs, rhs) => self.binary(lhs, rhs, Op::Greater),
+                    LogExpr::Less(lhs, rhs) => self.binary(lhs, rhs, Op::Less),
+                    LogExpr::Val(value) => self.emit(Op::Number(*value as u8 as f64)),
+                }
+                let jump = self.code.len();
+                self.emit(Op::JumpUnless(0));
+                self.body(body, tail, "If");
+                self.code[jump] = Op::JumpUnless(self.code.len());
+            }
+            Token::Filled(color, body) => {
+                self.emit(Op::Filled);
+                self.each(&[Input::Color(color.clone())], Op::Outline);
+                self.body(body, false, "Filled");
+                self.emit(Op::EndFilled);
+            }
+            Token::Tell(ids) => {
+                ids.iter().for_each(|id| self.expr(id));
+                self.emit(Op::Tell(ids.len()));
+            }
+            Token::Ask(ids, body) => {
+                ids.iter().for_each(|id| self.expr(id));
+                self.emit(Op::Ask(ids.len()));
+                self.body(body, false, "Ask");
+                self.emit(Op::EndAsk);
+            }
+            Token::NewTurtle => self.emit(Op::NewTurtle),
+            Token::Wrap => self.emit(Op::Boundary(Boundary::Wrap)),
+            Token::Window => self.emit(Op::Boundary(Boundary::Window)),
+            Token::Fence => self.emit(Op::Boundary(Boundary::Fence)),
+            Token::Source(span) => {
+                self.span = *span;
+                self.start = true;
+            }
+            Token::Comment(..) => {}
+            Token::Stop if self.in_procedure => self.emit(Op::Return),
+            Token::Stop => {
+                self.breaks.push(self.code.len());
+                self.emit(Op::Break(0));
+            }
+            _ => panic!("Unsupported token in execute: {:?}", token),
+        }
+    }
+}
@@ -0,0 +1,258 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+
+use crate::interpreter::lexer::Span;
+use crate::interpreter::parser::parse_all;
+use crate::interpreter::parser_types::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The program cannot run
+    Error,
+    /// The program runs, but likely not as intended
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// Problem found in a program without running it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Span,
+}
+
+impl Diagnostic {
+    fn warning(span: Span, message: String) -> Self {
+        Self {
+            severity: Severity::Warning,
+            message,
+            span,
+        }
+    }
+}
+
+/// Finds the problems of a program without running it. Mistakes stopping it
+/// from running, like calls to undefined procedures, wrong numbers of inputs
+/// or inputs that were never declared, are errors. Once there are none,
+/// unused inputs, instructions after `stop` and procedures calling themselves,
+/// directly or through others, with no way to stop are warned about.
+pub fn check(input: &str) -> Vec<Diagnostic> {
+    let (commands, fns, errors) = parse_all(input);
+    if !errors.is_empty() {
+        return errors
+            .into_iter()
+            .map(|error| Diagnostic {
+                severity: Severity::Error,
+                message: error.message,
+                span: error.span,
+            })
+            .collect();
+    }
+
+    let mut diagnostics = vec![];
+    after_stop(&commands, &mut diagnostics);
+    let mut calls = HashMap::new();
+    for (label, fun) in &fns.functions {
+        unused_inputs(label, fun, &mut diagnostics);
+        after_stop(&fun.body, &mut diagnostics);
+        let mut found = vec![];
+        sure_calls(&fun.body, &mut found);
+        calls.insert(label.as_str(), found);
+    }
+    for label in fns.functions.keys() {
+        if let Some((span, through)) = cycle(label, &calls) {
+            let message = match through.as_slice() {
+                [] => format!("'{}' calls itself with no way to stop", label),
+                _ => format!(
+                    "'{}' calls itself through '{}' with no way to stop",
+                    label,
+                    through.join("', '")
+                ),
+            };
+            diagnostics.push(Diagnostic::warning(span, message));
+        }
+    }
+    diagnostics.sort_by_key(|diagnostic| diagnostic.span.start);
+    diagnostics
+}
+
+/// Instructions of the block run by a control instruction
+fn block(token: &Token) -> Option<&[Token]> {
+    match token {
+        Token::Repeat(_, body)
+        | Token::If(_, body)
+        | Token::Filled(_, body)
+        | Token::Ask(_, body) => block(body),
+        Token::Bracket(tokens) => Some(tokens),
+        _ => None,
+    }
+}
+
+/// Expressions giving the inputs of an instruction, without those in its block
+fn exprs(token: &Token) -> Vec<&Expr> {
+    match token {
+        Token::Forward(expr)
+        | Token::Back(expr)
+        | Token::TurnRight(expr)
+        | Token::TurnLeft(expr)
+        | Token::Circle(expr)
+        | Token::Repeat(expr, _)
+        | Token::Expression(expr)
+        | Token::SetPenSize(expr)
+        | Token::SetLabelHeight(expr)
+        | Token::SetPenColor(Color::Index(expr))
+        | Token::SetFillColor(Color::Index(expr))
+        | Token::Filled(Color::Index(expr), _)
+        | Token::Label(Word::Number(expr))
+        | Token::SetLabelFont(Word::Number(expr)) => vec![expr],
+        Token::Arc(lhs, rhs)
+        | Token::Ellipse(lhs, rhs)
+        | Token::If(LogExpr::Greater(lhs, rhs), _)
+        | Token::If(LogExpr::Less(lhs, rhs), _) => vec![lhs, rhs],
+        Token::FnCall(_, args) => args.iter().collect(),
+        Token::Tell(ids) | Token::Ask(ids, _) => ids.iter().collect(),
+        _ => vec![],
+    }
+}
+
+/// Collects the names of the inputs an expression refers to
+fn names<'a>(expr: &'a Expr, used: &mut HashSet<&'a str>) {
+    match expr {
+        Expr::Arg(Arg::Param(param)) | Expr::Count(List::Param(param)) => {
+            used.insert(param);
+        }
+        Expr::Item(index, list) => {
+            if let List::Param(param) = list {
+                used.insert(param);
+            }
+            names(index, used);
+        }
+        Expr::Mul(lhs, rhs) | Expr::Div(lhs, rhs) | Expr::Add(lhs, rhs) | Expr::Sub(lhs, rhs) => {
+            names(lhs, used);
+            names(rhs, used);
+        }
+        Expr::Arg(_) | Expr::Count(_) => {}
+    }
+}
+
+fn used_names<'a>(tokens: &'a [Token], used: &mut HashSet<&'a str>) {
+    for token in tokens {
+        exprs(token).into_iter().for_each(|expr| names(expr, used));
+        if let Some(tokens) = block(token) {
+            used_names(tokens, used);
+        }
+    }
+}
+
+fn unused_inputs(label: &str, fun: &Fun, diagnostics: &mut Vec<Diagnostic>) {
+    let mut used = HashSet::new();
+    used_names(&fun.body, &mut used);
+    fun.defaults.iter().for_each(|expr| names(expr, &mut used));
+
+    for param in fun.params.iter().chain(&fun.rest) {
+        if !used.contains(param.as_str()) {
+            let message = format!("Input {} of '{}' is never used", param, label);
+            diagnostics.push(Diagnostic::warning(fun.span, message));
+        }
+    }
+}
+
+/// Warns about the instructions following a `stop` in the same block
+fn after_stop(tokens: &[Token], diagnostics: &mut Vec<Diagnostic>) {
+    let mut stopped = false;
+    for token in tokens {
+        match token {
+            Token::Stop => stopped = true,
+            Token::Source(span) if stopped => {
+                let message = String::from("Instructions after stop are never run");
+                diagnostics.push(Diagnostic::warning(*span, message));
+                stopped = false;
+            }
+            _ => {}
+        }
+        if let Some(tokens) = block(token) {
+            after_stop(tokens, diagnostics);
+        }
+    }
+}
+
+/// Where running a block leads when going through no `if`
+enum Path {
+    /// The block may end the procedure
+    Stop,
+    Through,
+}
+
+/// Collects the procedures a block always calls before any chance of
+/// stopping, with the span of the instruction making each call
+fn sure_calls<'a>(tokens: &'a [Token], found: &mut Vec<(&'a str, Span)>) -> Path {
+    let mut span = Span::default();
+    for token in tokens {
+        match token {
+            Token::Source(source) => span = *source,
+            Token::If(..) | Token::Stop => return Path::Stop,
+            Token::FnCall(name, _) => found.push((name, span)),
+            // The calls of a body that may not run are not sure to be made
+            Token::Repeat(times, body) if !runs_body(times) => {
+                if let Path::Stop = sure_calls(block(body).unwrap_or_default(), &mut vec![]) {
+                    return Path::Stop;
+                }
+            }
+            _ => {
+                if let Some(tokens) = block(token) {
+                    if let Path::Stop = sure_calls(tokens, found) {
+                        return Path::Stop;
+                    }
+                }
+            }
+        }
+    }
+    Path::Through
+}
+
+/// Whether a `repeat` given this number of times surely runs its body
+fn runs_body(times: &Expr) -> bool {
+    matches!(times, Expr::Arg(Arg::Val(times)) if *times >= 1.0)
+}
+
+/// Finds the shortest chain of sure calls leading from a procedure back to
+/// itself. Gives the span of the first call and the procedures called on
+/// the way.
+fn cycle<'a>(
+    label: &'a str,
+    calls: &HashMap<&'a str, Vec<(&'a str, Span)>>,
+) -> Option<(Span, Vec<&'a str>)> {
+    // The caller of each procedure reached
+    let mut callers: HashMap<&str, &str> = HashMap::new();
+    let mut queue = VecDeque::from([label]);
+    while let Some(caller) = queue.pop_front() {
+        for &(callee, _) in calls.get(caller).into_iter().flatten() {
+            if callee == label {
+                let mut through = vec![];
+                let mut at = caller;
+                while at != label {
+                    through.push(at);
+                    at = callers[at];
+                }
+                through.reverse();
+                let first = through.first().copied().unwrap_or(label);
+                let span = calls[label].iter().find(|(name, _)| *name == first)?.1;
+                return Some((span, through));
+            }
+            if callee != label && !callers.contains_key(callee) {
+                callers.insert(callee, caller);
+                queue.push_back(callee);
+            }
+        }
+    }
+    None
+}
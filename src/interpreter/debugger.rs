@@ -0,0 +1,234 @@
+use crate::interpreter::bytecode::*;
+use crate::interpreter::image::*;
+use crate::interpreter::turtle::TurtleState;
+use crate::interpreter::vm::*;
+use crate::interpreter::*;
+
+/// Where the debugger pauses the program
+#[derive(Debug, Clone, PartialEq)]
+pub enum Breakpoint {
+    /// Before the first instruction run on the line, numbered from 1
+    Line(usize),
+    /// Before the first instruction of the procedure, on every call
+    Procedure(String),
+}
+
+/// Why the program paused
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pause {
+    Breakpoint(Breakpoint),
+    /// The step asked for is done
+    Step,
+    /// The program ran to its end, or stopped with an error
+    Finished(Result<(), LogoError>),
+}
+
+/// A procedure being run, or the top level commands
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallFrame {
+    /// `None` for the top level commands
+    pub procedure: Option<String>,
+    /// Values bound to the inputs, in the order of the definition
+    pub inputs: Vec<(String, f64)>,
+    /// Values gathered by the rest input
+    pub rest: Option<(String, Vec<f64>)>,
+    /// Instruction the frame is at: the next one to run for the innermost
+    /// frame, the call to the procedure above for the others
+    pub span: Span,
+}
+
+/// Runs a program one instruction at a time. It starts paused before the
+/// first instruction, each step or resumption runs until the next pause.
+pub struct Debugger {
+    program: Program,
+    vm: Vm,
+    image: Image,
+    /// Line of each op, counted from 1
+    lines: Vec<usize>,
+    breakpoints: Vec<Breakpoint>,
+    /// Line of the last instruction reached, a line breakpoint only pauses
+    /// when coming from another line
+    line: usize,
+    result: Option<Result<(), LogoError>>,
+}
+
+impl Debugger {
+    pub fn new(input: &str, options: &Options) -> Result<Self, ParseError> {
+        let (commands, fns) = parse(input)?;
+        let program = compile(&commands, &fns);
+
+        let newlines: Vec<usize> = input.match_indices('\n').map(|(i, _)| i).collect();
+        let lines = program
+            .spans
+            .iter()
+            .map(|span| newlines.partition_point(|&newline| newline < span.start) + 1)
+            .collect();
+
+        let mut image = Image::new();
+        image.set_precision(options.precision);
+        image.set_limits(&options.limits);
+        let mut debugger = Self {
+            program,
+            vm: Vm::new(options.limits.clone()),
+            image,
+            lines,
+            breakpoints: vec![],
+            line: 0,
+            result: None,
+        };
+        debugger.line = debugger.lines[0];
+        Ok(debugger)
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        if !self.breakpoints.contains(&breakpoint) {
+            self.breakpoints.push(breakpoint);
+        }
+    }
+
+    /// Removes a breakpoint, telling whether it was set
+    pub fn remove_breakpoint(&mut self, breakpoint: &Breakpoint) -> bool {
+        let count = self.breakpoints.len();
+        self.breakpoints.retain(|other| other != breakpoint);
+        self.breakpoints.len() < count
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    /// Runs until a breakpoint or the end of the program
+    pub fn resume(&mut self) -> Pause {
+        self.run(|_| false)
+    }
+
+    /// Runs the next instruction, pausing inside the procedure it calls
+    pub fn step_in(&mut self) -> Pause {
+        self.run(|_| true)
+    }
+
+    /// Runs the next instruction along with the procedures it calls
+    pub fn step_over(&mut self) -> Pause {
+        let depth = self.depth();
+        self.run(|debugger| debugger.depth() <= depth)
+    }
+
+    /// Runs until the current procedure returns
+    pub fn step_out(&mut self) -> Pause {
+        let depth = self.depth();
+        self.run(|debugger| debugger.depth() < depth)
+    }
+
+    /// Whether the program has run to its end or stopped with an error
+    pub fn finished(&self) -> bool {
+        self.result.is_some()
+    }
+
+    /// Source of the next instruction, `None` once the program has finished
+    pub fn span(&self) -> Option<Span> {
+        match self.result {
+            Some(_) => None,
+            None => Some(self.program.spans[self.vm.pc()]),
+        }
+    }
+
+    /// Line of the next instruction, `None` once the program has finished
+    pub fn line(&self) -> Option<usize> {
+        self.span().map(|_| self.lines[self.vm.pc()])
+    }
+
+    /// Procedures being run, innermost first, ending with the top level,
+    /// empty once the program has finished
+    pub fn stack(&self) -> Vec<CallFrame> {
+        let Some(mut span) = self.span() else {
+            return vec![];
+        };
+        let mut frames = vec![];
+        let calls: Vec<_> = self.vm.calls().collect();
+        for &(index, ret, stack) in calls.iter().rev() {
+            let procedure = &self.program.procedures[index];
+            let inputs = procedure.params.iter().cloned().zip(stack.iter().copied());
+            let rest = procedure.rest.as_ref().map(|rest| {
+                // The rest input follows the others, starting with its length
+                let count = stack[procedure.params.len()] as usize;
+                let start = procedure.params.len() + 1;
+                (rest.clone(), stack[start..start + count].to_vec())
+            });
+            frames.push(CallFrame {
+                procedure: Some(procedure.name.clone()),
+                inputs: inputs.collect(),
+                rest,
+                span,
+            });
+            span = self.program.spans[ret - 1];
+        }
+        frames.push(CallFrame {
+            procedure: None,
+            inputs: vec![],
+            rest: None,
+            span,
+        });
+        frames
+    }
+
+    /// State of the turtle the next instruction applies to
+    pub fn turtle(&self) -> TurtleState {
+        self.vm.turtle().state()
+    }
+
+    /// Saves the drawing made so far
+    pub fn save(&self, image_path: &str) -> Result<(), LogoError> {
+        self.image.save(image_path)
+    }
+
+    fn depth(&self) -> usize {
+        self.vm.calls().count()
+    }
+
+    /// Runs ops until the start of an instruction where a breakpoint is set
+    /// or where `done` holds
+    fn run(&mut self, done: impl Fn(&Self) -> bool) -> Pause {
+        if let Some(result) = &self.result {
+            return Pause::Finished(result.clone());
+        }
+        loop {
+            let call = matches!(
+                self.program.code[self.vm.pc()],
+                Op::Call(..) | Op::TailCall(..)
+            );
+            match self.vm.step(&self.program, &mut self.image) {
+                Ok(true) => {}
+                result => {
+                    if result.is_err() {
+                        self.vm.abandon(&mut self.image);
+                    }
+                    self.vm.show(&mut self.image);
+                    let result = result.map(|_| ());
+                    self.result = Some(result.clone());
+                    return Pause::Finished(result);
+                }
+            }
+
+            let pc = self.vm.pc();
+            if !self.program.starts[pc] {
+                continue;
+            }
+            let line = self.lines[pc];
+            let entered = std::mem::replace(&mut self.line, line) != line;
+            let procedure = match self.vm.calls().last() {
+                Some((index, ..)) if call => Some(&self.program.procedures[index].name),
+                _ => None,
+            };
+            let breakpoint = self.breakpoints.iter().find(|breakpoint| match breakpoint {
+                Breakpoint::Line(number) => entered && *number == line,
+                Breakpoint::Procedure(name) => procedure == Some(name),
+            });
+            if let Some(breakpoint) = breakpoint {
+                return Pause::Breakpoint(breakpoint.clone());
+            }
+            if done(self) {
+                return Pause::Step;
+            }
+        }
+    }
+}
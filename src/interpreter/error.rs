@@ -0,0 +1,57 @@
+use std::fmt;
+
+use crate::interpreter::lexer::Span;
+use crate::interpreter::limits::Limit;
+
+/// Error raised while running a Logo program
+#[derive(Debug, Clone, PartialEq)]
+pub enum LogoError {
+    /// The source is not a valid program
+    Parse(ParseError),
+    /// The turtle tried to leave the drawing area in fence mode
+    OutOfBounds,
+    /// A distance, angle, radius or size is infinite or not a number, as when
+    /// dividing by zero
+    NotFinite,
+    /// The program used up one of the configured limits
+    LimitExceeded(Limit),
+    /// The trace file could not be created or written, for the given reason
+    Trace(String),
+    /// The drawing could not be saved, with the file and the reason
+    Io(String),
+}
+
+impl fmt::Display for LogoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LogoError::Parse(error) => write!(f, "Parse error: {}", error),
+            LogoError::OutOfBounds => write!(f, "Turtle out of bounds"),
+            LogoError::NotFinite => write!(f, "Input is not a finite number"),
+            LogoError::LimitExceeded(limit) => write!(f, "Limit exceeded: {}", limit),
+            LogoError::Trace(reason) => write!(f, "Unable to write trace file: {}", reason),
+            LogoError::Io(reason) => write!(f, "Unable to save {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for LogoError {}
+
+/// Mistake found in the source of a program before running it
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    /// Where in the source the mistake is
+    pub span: Span,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl From<ParseError> for LogoError {
+    fn from(error: ParseError) -> Self {
+        LogoError::Parse(error)
+    }
+}
@@ -0,0 +1,260 @@
+use crate::interpreter::error::ParseError;
+use crate::interpreter::lexer::Span;
+use crate::interpreter::parser::parse;
+use crate::interpreter::parser_types::*;
+
+const INDENT: &str = "  ";
+
+/// Rewrites a program in the canonical layout: one instruction per line,
+/// blocks and procedure bodies indented, and primitives under their full
+/// names. Comments stay where they were, on the line of the instruction they
+/// followed when they shared it.
+pub fn format(input: &str) -> Result<String, ParseError> {
+    let (commands, fns) = parse(input)?;
+    let mut definitions: Vec<_> = fns.functions.iter().collect();
+    definitions.sort_by_key(|(_, fun)| fun.span.start);
+    let mut definitions = definitions.into_iter().peekable();
+
+    let mut formatter = Formatter {
+        input,
+        fns: &fns,
+        lines: vec![],
+        depth: 0,
+        source: None,
+    };
+    for token in &commands {
+        // Definitions go back among the top-level commands where they were written
+        if let Token::Source(span) | Token::Comment(_, span) = token {
+            while let Some((label, fun)) =
+                definitions.next_if(|(_, fun)| fun.span.start < span.start)
+            {
+                formatter.definition(label, fun);
+            }
+        }
+        formatter.token(token);
+    }
+    for (label, fun) in definitions {
+        formatter.definition(label, fun);
+    }
+
+    while formatter.lines.last().is_some_and(|line| line.is_empty()) {
+        formatter.lines.pop();
+    }
+    Ok(formatter
+        .lines
+        .into_iter()
+        .map(|line| line + "\n")
+        .collect())
+}
+
+struct Formatter<'a> {
+    input: &'a str,
+    fns: &'a Functions,
+    lines: Vec<String>,
+    /// Blocks the current line is nested in
+    depth: usize,
+    /// Where the last instruction written is in the source, comments within
+    /// it or on the line it ends stay next to it
+    source: Option<Span>,
+}
+
+impl Formatter<'_> {
+    fn line(&mut self, text: String) {
+        self.lines.push(INDENT.repeat(self.depth) + &text);
+    }
+
+    fn definition(&mut self, label: &str, fun: &Fun) {
+        // Definitions are set apart from the instructions around them, but
+        // stay with the comments introducing them
+        if let Some(line) = self.lines.last() {
+            if !line.is_empty() && !line.trim_start().starts_with(';') {
+                self.lines.push(String::new());
+            }
+        }
+
+        self.line(signature(label, fun));
+        self.source = Some(fun.span);
+
+        self.depth += 1;
+        fun.body.iter().for_each(|token| self.token(token));
+        self.depth -= 1;
+        self.line(String::from("end"));
+        self.lines.push(String::new());
+        self.source = None;
+    }
+
+    fn token(&mut self, token: &Token) {
+        match token {
+            Token::Source(span) => self.source = Some(*span),
+            Token::Comment(text, span) => {
+                let same_line = self.source.is_some_and(|source| {
+                    span.start < source.end || !self.input[source.end..span.start].contains('\n')
+                });
+                match self.lines.last_mut() {
+                    Some(line) if same_line => *line += &format!(" ;{}", text),
+                    _ => self.line(format!(";{}", text)),
+                }
+                self.source = None;
+            }
+            _ => {
+                // The instructions of its block leave the whole instruction last
+                let source = self.source;
+                self.instruction(token);
+                self.source = source;
+            }
+        }
+    }
+
+    /// Writes a control instruction with its block
+    fn block(&mut self, header: String, body: &Token) {
+        let tokens = match body {
+            Token::Bracket(tokens) => tokens,
+            _ => panic!("Block that is not a Bracket token"),
+        };
+        if tokens.is_empty() {
+            return self.line(format!("{}[]", header));
+        }
+        self.line(format!("{}[", header));
+        self.depth += 1;
+        tokens.iter().for_each(|token| self.token(token));
+        self.depth -= 1;
+        self.line(String::from("]"));
+    }
+
+    fn instruction(&mut self, token: &Token) {
+        let text = match token {
+            Token::Forward(distance) => format!("forward {}", expr(distance)),
+            Token::Back(distance) => format!("back {}", expr(distance)),
+            Token::TurnRight(angle) => format!("right {}", expr(angle)),
+            Token::TurnLeft(angle) => format!("left {}", expr(angle)),
+            Token::Arc(angle, radius) => format!("arc {} {}", expr(angle), expr(radius)),
+            Token::Circle(radius) => format!("circle {}", expr(radius)),
+            Token::Ellipse(width, height) => {
+                format!("ellipse {} {}", expr(width), expr(height))
+            }
+            Token::FnCall(label, args) => {
+                let call = args
+                    .iter()
+                    .fold(label.clone(), |call, arg| call + " " + &expr(arg));
+                // Parentheses are only needed to give a different number of inputs
+                match self.fns.get(label) {
+                    Some(fun) if fun.arity() == args.len() => call,
+                    _ => format!("({})", call),
+                }
+            }
+            Token::Expression(value) => expr(value),
+            Token::Wrap => String::from("wrap"),
+            Token::Window => String::from("window"),
+            Token::Fence => String::from("fence"),
+            Token::SetPenColor(color) => format!("setpencolor {}", self::color(color)),
+            Token::SetPenSize(size) => format!("setpensize {}", expr(size)),
+            Token::Tell(ids) => format!("tell {}", turtle_ids(ids)),
+            Token::NewTurtle => String::from("newturtle"),
+            Token::ShowTurtle => String::from("showturtle"),
+            Token::HideTurtle => String::from("hideturtle"),
+            Token::PenUp => String::from("penup"),
+            Token::PenDown => String::from("pendown"),
+            Token::SetFillColor(color) => format!("setfillcolor {}", self::color(color)),
+            Token::Fill => String::from("fill"),
+            Token::Label(text) => format!("label {}", word(text)),
+            Token::SetLabelHeight(height) => format!("setlabelheight {}", expr(height)),
+            Token::SetLabelFont(font) => format!("setlabelfont {}", word(font)),
+            Token::Stop => String::from("stop"),
+            Token::Repeat(times, body) => {
+                return self.block(format!("repeat {} ", expr(times)), body);
+            }
+            Token::If(log_expr, body) => {
+                let condition = match log_expr {
+                    LogExpr::Greater(lhs, rhs) => format!("{} > {}", expr(lhs), expr(rhs)),
+                    LogExpr::Less(lhs, rhs) => format!("{} < {}", expr(lhs), expr(rhs)),
+                    LogExpr::Val(value) => format!("{} > 0", *value as u8),
+                };
+                return self.block(format!("if {} ", condition), body);
+            }
+            Token::Ask(ids, body) => {
+                return self.block(format!("ask {} ", turtle_ids(ids)), body);
+            }
+            Token::Filled(fill, body) => {
+                return self.block(format!("filled {} ", color(fill)), body);
+            }
+            Token::Bracket(_) => return self.block(String::new(), token),
+            Token::Comment(..) | Token::Source(_) => return self.token(token),
+        };
+        self.line(text);
+    }
+}
+
+/// First line of a definition: `to`, the name and the inputs
+pub fn signature(label: &str, fun: &Fun) -> String {
+    let mut header = format!("to {}", label);
+    for (i, param) in fun.params.iter().enumerate() {
+        match i.checked_sub(fun.arity()) {
+            Some(optional) => {
+                let default = expr(&fun.defaults[optional]);
+                header += &format!(" [{} {}]", param, default);
+            }
+            None => header += &format!(" {}", param),
+        }
+    }
+    if let Some(rest) = &fun.rest {
+        header += &format!(" [{}]", rest);
+    }
+    header
+}
+
+/// Writes an expression back as it is parsed: operators all have the same
+/// precedence and group from the right
+fn expr(expr: &Expr) -> String {
+    let binary = |lhs: &Expr, op: &str, rhs: &Expr| {
+        format!("{} {} {}", self::expr(lhs), op, self::expr(rhs))
+    };
+    match expr {
+        Expr::Arg(Arg::Val(value)) => value.to_string(),
+        Expr::Arg(Arg::Param(param)) => param.clone(),
+        Expr::Arg(Arg::Who) => String::from("who"),
+        Expr::Count(values) => format!("count {}", list(values)),
+        Expr::Item(index, values) => format!("item {} {}", self::expr(index), list(values)),
+        Expr::Mul(lhs, rhs) => binary(lhs, "*", rhs),
+        Expr::Div(lhs, rhs) => binary(lhs, "/", rhs),
+        Expr::Add(lhs, rhs) => binary(lhs, "+", rhs),
+        Expr::Sub(lhs, rhs) => binary(lhs, "-", rhs),
+    }
+}
+
+fn list(list: &List) -> String {
+    match list {
+        List::Param(param) => param.clone(),
+        List::Values(values) => {
+            let values: Vec<_> = values.iter().map(|value| value.to_string()).collect();
+            format!("[{}]", values.join(" "))
+        }
+    }
+}
+
+fn color(color: &Color) -> String {
+    match color {
+        Color::Name(name) => format!("\"{}", name),
+        Color::Index(index) => expr(index),
+    }
+}
+
+/// Single words are quoted, others kept in brackets
+fn word(word: &Word) -> String {
+    match word {
+        Word::Literal(text) if !text.is_empty() && !text.contains(char::is_whitespace) => {
+            format!("\"{}", text)
+        }
+        Word::Literal(text) => format!("[{}]", text),
+        Word::Number(value) => expr(value),
+    }
+}
+
+fn turtle_ids(ids: &[Expr]) -> String {
+    match ids {
+        [id] => expr(id),
+        _ => {
+            let ids: Vec<_> = ids.iter().map(expr).collect();
+            format!("[{}]", ids.join(" "))
+        }
+    }
+}
@@ -1,45 +1,712 @@
+use std::fs;
+use std::io;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use serde_json::json;
 use svg::node::element::Rectangle;
-use svg::node::element::{Group, Line};
-use svg::Document;
+use svg::node::element::{Animate, AnimateMotion, Group, Path, Text};
+use svg::{Document, Node};
+
+use crate::interpreter::error::LogoError;
+use crate::interpreter::lexer::Span;
+use crate::interpreter::limits::*;
+use crate::interpreter::raster::*;
+use crate::interpreter::recording::{self, Recording};
+use crate::interpreter::turtle::TurtleState;
+
+/// Size of the drawing area, centered on the origin
+pub const WIDTH: f64 = 800.0;
+pub const HEIGHT: f64 = 800.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stroke {
+    /// Shared by the elements drawn with it, which are many
+    pub color: Rc<str>,
+    pub width: f64,
+}
+
+impl Stroke {
+    pub fn new() -> Self {
+        Self {
+            color: Rc::from("black"),
+            width: 1.0,
+        }
+    }
+}
+
+/// Replays the drawing in the order the segments were made
+#[derive(Debug, Clone)]
+pub struct Animation {
+    /// Drawing speed in units per second
+    pub speed: f64,
+    /// Show a turtle moving along the drawn path
+    pub sprite: bool,
+}
+
+/// Instruction that drew an element, with the procedure calls it was run from
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Origin {
+    pub span: Span,
+    /// Procedures being run, outermost first, each with the instruction
+    /// calling it
+    pub calls: Vec<(String, Span)>,
+}
+
+#[derive(Debug, Clone)]
+struct Segment {
+    x1: f64,
+    y1: f64,
+    x2: f64,
+    y2: f64,
+}
+
+/// Part of an ellipse, angles in degrees measured clockwise from the x axis
+#[derive(Debug, Clone)]
+pub struct Arc {
+    pub center: (f64, f64),
+    pub radii: (f64, f64),
+    /// Rotation of the ellipse axes
+    pub rotation: f64,
+    /// Angle of the starting point, relative to the rotated axes
+    pub start: f64,
+    /// Angle covered by the arc, negative values go anticlockwise
+    pub sweep: f64,
+}
+
+impl Arc {
+    fn point(&self, angle: f64) -> (f64, f64) {
+        let (sin, cos) = angle.to_radians().sin_cos();
+        let (rot_sin, rot_cos) = self.rotation.to_radians().sin_cos();
+        let (x, y) = (self.radii.0 * cos, self.radii.1 * sin);
+        (
+            self.center.0 + x * rot_cos - y * rot_sin,
+            self.center.1 + x * rot_sin + y * rot_cos,
+        )
+    }
+
+    /// Approximates the arc with a point every degree
+    fn points(&self) -> Vec<(f64, f64)> {
+        let steps = (self.sweep.abs().ceil() as usize).max(1);
+        (0..=steps)
+            .map(|i| self.point(self.start + self.sweep * i as f64 / steps as f64))
+            .collect()
+    }
+
+    fn length(&self) -> f64 {
+        polyline_length(&self.points())
+    }
+}
+
+/// Text written along the turtle's heading
+#[derive(Debug, Clone)]
+pub struct Label {
+    pub position: (f64, f64),
+    /// Clockwise rotation of the baseline in degrees
+    pub rotation: f64,
+    pub text: String,
+    pub height: f64,
+    pub font: String,
+}
+
+#[derive(Debug, Clone)]
+enum Element {
+    Line(Segment, Stroke),
+    Arc(Arc, Stroke),
+    Polygon(Vec<(f64, f64)>, String, Stroke),
+    /// Paint bucket fill, only visible in raster output
+    Fill((f64, f64), String),
+    Label(Label, Stroke),
+}
+
+/// Stroked outline ready to be written as a single path
+struct Outline<'a> {
+    data: String,
+    length: f64,
+    stroke: &'a Stroke,
+    fill: Option<&'a str>,
+}
+
+enum Shape<'a> {
+    Outline(Outline<'a>),
+    Label(&'a Label, &'a Stroke),
+}
+
+fn polyline_length(points: &[(f64, f64)]) -> f64 {
+    points
+        .windows(2)
+        .map(|pair| (pair[1].0 - pair[0].0).hypot(pair[1].1 - pair[0].1))
+        .sum()
+}
 
 pub struct Image {
-    lines: Vec<Line>,
+    elements: Vec<Element>,
+    precision: usize,
+    animation: Option<Animation>,
+    max_segments: Option<usize>,
+    /// Time limit, with the moment the program started
+    time: Option<(Duration, Instant)>,
+    turtles: Vec<(f64, f64, f64)>,
+    /// Program drawing the image, when elements are tagged with their origin
+    source: Option<String>,
+    /// Origin of the elements drawn next
+    origin: Rc<Origin>,
+    /// Origin of each element, when tagged
+    origins: Vec<Rc<Origin>>,
 }
 
 impl Image {
     pub fn new() -> Self {
-        Self { lines: vec![] }
+        Self {
+            elements: vec![],
+            precision: 2,
+            animation: None,
+            max_segments: None,
+            time: None,
+            turtles: vec![],
+            source: None,
+            origin: Rc::default(),
+            origins: vec![],
+        }
+    }
+
+    /// Tags the SVG elements with the instruction and procedure calls of the
+    /// program that drew them. Lines drawn by different instructions are no
+    /// longer joined, so that each element has a single origin.
+    pub fn tag_origins(&mut self, source: &str) {
+        self.source = Some(source.to_string());
     }
 
-    pub fn add_line(&mut self, x1: f64, y1: f64, x2: f64, y2: f64) {
-        self.lines.push(
-            Line::new()
-                .set("x1", x1)
-                .set("y1", y1)
-                .set("x2", x2)
-                .set("y2", y2)
-                .set("stroke", "black"),
-        );
+    pub fn tagged(&self) -> bool {
+        self.source.is_some()
     }
 
-    pub fn save(&self, file_path: &str) {
+    /// Gives the origin of the elements drawn next, when tagged
+    pub fn set_origin(&mut self, origin: Origin) {
+        self.origin = Rc::new(origin);
+    }
+
+    fn push(&mut self, element: Element) {
+        self.elements.push(element);
+        if self.tagged() {
+            self.origins.push(Rc::clone(&self.origin));
+        }
+    }
+
+    /// Number of digits after the decimal point written for coordinates
+    pub fn set_precision(&mut self, precision: usize) {
+        self.precision = precision;
+    }
+
+    pub fn set_animation(&mut self, animation: Option<Animation>) {
+        self.animation = animation;
+    }
+
+    /// Limits on the segments drawn and the running time, which also bound
+    /// the work of a single move wrapping around the edges
+    pub fn set_limits(&mut self, limits: &Limits) {
+        self.max_segments = limits.segments;
+        self.time = limits.time.map(|max| (max, Instant::now()));
+    }
+
+    /// Checks the limits while a move wraps around the edges, counting every
+    /// piece of it whether the pen is down or not
+    pub fn wrapped(&self, pieces: usize) -> Result<(), LogoError> {
+        let exceeded = match (self.max_segments, self.time) {
+            (Some(max), _) if pieces > max => Limit::Segments(max),
+            (_, Some((max, started))) if started.elapsed() > max => Limit::Time(max),
+            _ => return Ok(()),
+        };
+        Err(LogoError::LimitExceeded(exceeded))
+    }
+
+    /// Number of lines, curves, fills and labels drawn so far
+    pub fn element_count(&self) -> usize {
+        self.elements.len()
+    }
+
+    /// Length and number of the lines, curves and filled outlines drawn since
+    /// the given element count
+    pub fn measure(&self, from: usize) -> (f64, usize) {
+        let mut drawn = (0.0, 0);
+        for element in &self.elements[from..] {
+            let (length, segments) = match element {
+                Element::Line(segment, _) => {
+                    let length = (segment.x2 - segment.x1).hypot(segment.y2 - segment.y1);
+                    (length, 1)
+                }
+                Element::Arc(arc, _) => (arc.length(), 1),
+                Element::Polygon(points, ..) => {
+                    let mut closed = points.clone();
+                    closed.extend(points.first());
+                    (polyline_length(&closed), points.len())
+                }
+                Element::Fill(..) | Element::Label(..) => (0.0, 0),
+            };
+            drawn.0 += length;
+            drawn.1 += segments;
+        }
+        drawn
+    }
+
+    /// The drawing as data, with the y axis pointing up, along with the final
+    /// state of the turtles
+    pub fn record(&self, turtles: Vec<TurtleState>) -> Recording {
+        let point = |(x, y): (f64, f64)| (x, 0.0 - y);
+        let style = |stroke: &Stroke| recording::Style {
+            color: stroke.color.to_string(),
+            width: stroke.width,
+        };
+        let mut recording = Recording {
+            turtles,
+            ..Recording::default()
+        };
+        for element in &self.elements {
+            match element {
+                Element::Line(segment, stroke) => recording.segments.push(recording::Segment {
+                    from: point((segment.x1, segment.y1)),
+                    to: point((segment.x2, segment.y2)),
+                    style: style(stroke),
+                }),
+                Element::Arc(arc, stroke) => recording.curves.push(recording::Curve {
+                    center: point(arc.center),
+                    radii: arc.radii,
+                    rotation: arc.rotation,
+                    start: arc.start,
+                    sweep: arc.sweep,
+                    style: style(stroke),
+                }),
+                Element::Polygon(points, fill, stroke) => {
+                    recording.fills.push(recording::Fill::Shape {
+                        points: points.iter().copied().map(point).collect(),
+                        color: fill.clone(),
+                        outline: style(stroke),
+                    })
+                }
+                Element::Fill(at, color) => recording.fills.push(recording::Fill::Flood {
+                    point: point(*at),
+                    color: color.clone(),
+                }),
+                Element::Label(label, stroke) => recording.labels.push(recording::Text {
+                    position: point(label.position),
+                    rotation: label.rotation,
+                    text: label.text.clone(),
+                    height: label.height,
+                    font: label.font.clone(),
+                    style: style(stroke),
+                }),
+            }
+        }
+        recording
+    }
+
+    /// Draws a turtle at the given position, facing the given angle
+    pub fn add_turtle(&mut self, x: f64, y: f64, angle: f64) {
+        self.turtles.push((x, y, angle));
+    }
+
+    pub fn add_line(&mut self, (x1, y1): (f64, f64), (x2, y2): (f64, f64), stroke: &Stroke) {
+        self.push(Element::Line(Segment { x1, y1, x2, y2 }, stroke.clone()));
+    }
+
+    pub fn add_arc(&mut self, arc: Arc, stroke: &Stroke) {
+        self.push(Element::Arc(arc, stroke.clone()));
+    }
+
+    /// Closed outline filled with the given color
+    pub fn add_polygon(&mut self, points: Vec<(f64, f64)>, fill: String, stroke: &Stroke) {
+        self.push(Element::Polygon(points, fill, stroke.clone()));
+    }
+
+    pub fn add_fill(&mut self, x: f64, y: f64, color: String) {
+        self.push(Element::Fill((x, y), color));
+    }
+
+    pub fn add_label(&mut self, label: Label, stroke: &Stroke) {
+        self.push(Element::Label(label, stroke.clone()));
+    }
+
+    fn format(&self, value: f64) -> String {
+        let text = format!("{:.*}", self.precision, value);
+        let text = if text.contains('.') {
+            text.trim_end_matches('0').trim_end_matches('.')
+        } else {
+            &text
+        };
+        match text {
+            "-0" => String::from("0"),
+            other => String::from(other),
+        }
+    }
+
+    fn point(&self, x: f64, y: f64) -> String {
+        format!("{} {}", self.format(x), self.format(y))
+    }
+
+    /// Joins consecutive segments drawn with the same stroke into shapes,
+    /// each with its origin when tagged. Segments continue a shape when their
+    /// start point is the end point of the previous one at the configured
+    /// precision.
+    fn shapes(&self) -> Vec<(Shape<'_>, Option<&Origin>)> {
+        let mut shapes = Vec::new();
+        let mut run: Vec<(f64, f64)> = Vec::new();
+        let mut run_stroke: Option<&Stroke> = None;
+        let mut run_origin = None;
+
+        for (i, element) in self.elements.iter().enumerate() {
+            let origin = self.origins.get(i).map(|origin| &**origin);
+            match element {
+                Element::Line(segment, stroke) => {
+                    let start = (segment.x1, segment.y1);
+                    let continues = run_stroke == Some(stroke)
+                        && run_origin == origin
+                        && run.last().is_some_and(|&(x, y)| {
+                            self.point(x, y) == self.point(start.0, start.1)
+                        });
+
+                    if !continues {
+                        if let Some(run_stroke) = run_stroke {
+                            let outline = self.polyline(&run, run_stroke);
+                            shapes.push((Shape::Outline(outline), run_origin));
+                        }
+                        run = vec![start];
+                        run_stroke = Some(stroke);
+                        run_origin = origin;
+                    }
+                    run.push((segment.x2, segment.y2));
+                }
+                Element::Fill(..) => {}
+                other => {
+                    if let Some(run_stroke) = run_stroke.take() {
+                        let outline = self.polyline(&run, run_stroke);
+                        shapes.push((Shape::Outline(outline), run_origin));
+                    }
+                    shapes.push((self.shape(other), origin));
+                }
+            }
+        }
+        if let Some(run_stroke) = run_stroke {
+            shapes.push((Shape::Outline(self.polyline(&run, run_stroke)), run_origin));
+        }
+
+        shapes
+    }
+
+    /// Identifies an element and gives the instruction and procedure calls
+    /// that drew it, the span as byte offsets into the program
+    fn tag<T: Node>(&self, mut node: T, id: usize, origin: Option<&Origin>) -> T {
+        if let Some(origin) = origin {
+            let calls: Vec<&str> = origin.calls.iter().map(|(name, _)| name.as_str()).collect();
+            node.assign("id", format!("e{}", id));
+            node.assign(
+                "data-span",
+                format!("{}-{}", origin.span.start, origin.span.end),
+            );
+            node.assign("data-stack", calls.join(" "));
+        }
+        node
+    }
+
+    /// Writes the origin of each tagged element, with its line and column
+    fn save_origins(&self, file_path: &str, source: &str) -> io::Result<()> {
+        let span = |span: Span| {
+            let (line, column) = span.position(source);
+            json!({"start": span.start, "end": span.end, "line": line, "column": column})
+        };
+        let elements: Vec<_> = self
+            .shapes()
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, (_, origin))| {
+                let origin = origin?;
+                let calls: Vec<_> = origin
+                    .calls
+                    .iter()
+                    .map(|(name, call)| json!({"procedure": name, "span": span(*call)}))
+                    .collect();
+                Some(json!({"id": format!("e{}", i), "span": span(origin.span), "stack": calls}))
+            })
+            .collect();
+        let text = serde_json::to_string_pretty(&elements)?;
+        fs::write(file_path, text + "\n")
+    }
+
+    /// Shape of an element that is never joined with its neighbours
+    fn shape<'a>(&self, element: &'a Element) -> Shape<'a> {
+        match element {
+            Element::Arc(arc, stroke) => Shape::Outline(Outline {
+                data: self.arc_data(arc),
+                length: arc.length(),
+                stroke,
+                fill: None,
+            }),
+            Element::Polygon(points, fill, stroke) => {
+                let mut closed = points.clone();
+                closed.extend(points.first());
+                let mut outline = self.polyline(&closed, stroke);
+                outline.data.push_str(" Z");
+                outline.fill = Some(fill);
+                Shape::Outline(outline)
+            }
+            Element::Label(label, stroke) => Shape::Label(label, stroke),
+            Element::Line(..) | Element::Fill(..) => unreachable!(),
+        }
+    }
+
+    fn polyline<'a>(&self, points: &[(f64, f64)], stroke: &'a Stroke) -> Outline<'a> {
+        let mut data = String::new();
+        for (i, &(x, y)) in points.iter().enumerate() {
+            data.push_str(if i == 0 { "M" } else { " L" });
+            data.push_str(&self.point(x, y));
+        }
+        Outline {
+            data,
+            length: polyline_length(points),
+            stroke,
+            fill: None,
+        }
+    }
+
+    /// Splits the arc into pieces of at most half a turn, so full circles and
+    /// ellipses stay unambiguous
+    fn arc_data(&self, arc: &Arc) -> String {
+        let pieces = (arc.sweep.abs() / 180.0).ceil().max(1.0) as usize;
+        let (x, y) = arc.point(arc.start);
+        let mut data = format!("M{}", self.point(x, y));
+        for piece in 1..=pieces {
+            let (x, y) = arc.point(arc.start + arc.sweep * piece as f64 / pieces as f64);
+            data.push_str(&format!(
+                " A{} {} {} 0 {} {}",
+                self.format(arc.radii.0),
+                self.format(arc.radii.1),
+                self.format(arc.rotation),
+                if arc.sweep > 0.0 { 1 } else { 0 },
+                self.point(x, y)
+            ));
+        }
+        data
+    }
+
+    fn path(&self, outline: &Outline) -> Path {
+        Path::new()
+            .set("d", outline.data.clone())
+            .set("fill", outline.fill.unwrap_or("none"))
+            .set("stroke", &*outline.stroke.color)
+            .set("stroke-width", self.format(outline.stroke.width))
+            .set("stroke-linejoin", "round")
+    }
+
+    fn text(&self, label: &Label, stroke: &Stroke) -> Text {
+        let (x, y) = label.position;
+        Text::new(label.text.clone())
+            .set("x", self.format(x))
+            .set("y", self.format(y))
+            .set("font-size", self.format(label.height))
+            .set("font-family", label.font.clone())
+            .set("fill", &*stroke.color)
+            .set(
+                "transform",
+                format!(
+                    "rotate({} {})",
+                    self.format(label.rotation),
+                    self.point(x, y)
+                ),
+            )
+    }
+
+    /// Classic turtle triangle pointing along the x axis
+    fn sprite(&self) -> Path {
+        Path::new()
+            .set("d", "M10 0 L-5 6 L-5 -6 Z")
+            .set("fill", "green")
+            .set("stroke", "darkgreen")
+    }
+
+    fn seconds(&self, seconds: f64) -> String {
+        format!("{}s", self.format(seconds.max(0.001)))
+    }
+
+    fn animated(&self, animation: &Animation) -> Group {
         let mut group = Group::new();
-        for line in &self.lines {
-            group = group.add(line.clone());
+        let mut begin = 0.0;
+        let mut trail = vec![];
+
+        for (i, (shape, origin)) in self.shapes().into_iter().enumerate() {
+            let outline = match shape {
+                Shape::Outline(outline) => outline,
+                Shape::Label(label, stroke) => {
+                    // Appears when the pen reaches it
+                    let text = self.tag(self.text(label, stroke), i, origin);
+                    group = group.add(
+                        text.set("opacity", 0).add(
+                            Animate::new()
+                                .set("attributeName", "opacity")
+                                .set("from", 0)
+                                .set("to", 1)
+                                .set("begin", self.seconds(begin))
+                                .set("dur", self.seconds(0.0))
+                                .set("fill", "freeze"),
+                        ),
+                    );
+                    continue;
+                }
+            };
+            let duration = outline.length / animation.speed;
+            let dash = self.format(outline.length);
+
+            // Hidden behind a full-length dash gap until its turn comes
+            let mut path = self
+                .tag(self.path(&outline), i, origin)
+                .set("stroke-dasharray", dash.clone())
+                .set("stroke-dashoffset", dash.clone())
+                .add(
+                    Animate::new()
+                        .set("attributeName", "stroke-dashoffset")
+                        .set("from", dash)
+                        .set("to", 0)
+                        .set("begin", self.seconds(begin))
+                        .set("dur", self.seconds(duration))
+                        .set("fill", "freeze"),
+                );
+            if outline.fill.is_some() {
+                // Filled once the outline is complete
+                path = path.set("fill-opacity", 0).add(
+                    Animate::new()
+                        .set("attributeName", "fill-opacity")
+                        .set("from", 0)
+                        .set("to", 1)
+                        .set("begin", self.seconds(begin + duration))
+                        .set("dur", self.seconds(0.0))
+                        .set("fill", "freeze"),
+                );
+            }
+            group = group.add(path);
+
+            begin += duration;
+            trail.push(outline.data);
+        }
+
+        if animation.sprite && !trail.is_empty() {
+            // Jumps between runs have no length, so a paced motion keeps the
+            // sprite in step with the drawing
+            group = group.add(
+                self.sprite().add(
+                    AnimateMotion::new()
+                        .set("path", trail.join(" "))
+                        .set("dur", self.seconds(begin))
+                        .set("rotate", "auto")
+                        .set("calcMode", "paced")
+                        .set("fill", "freeze"),
+                ),
+            );
+        }
+
+        group
+    }
+
+    /// Writes a PNG when the path ends in `.png`, an SVG otherwise. Tagged
+    /// SVG images come with a JSON file named after them, adding `.json`,
+    /// which lists the origin of each element by id.
+    pub fn save(&self, file_path: &str) -> Result<(), LogoError> {
+        let failed = |path: &str, error: io::Error| LogoError::Io(format!("{}: {}", path, error));
+        if file_path.ends_with(".png") {
+            return self
+                .save_png(file_path)
+                .map_err(|error| failed(file_path, error));
+        }
+        svg::save(file_path, &self.document()).map_err(|error| failed(file_path, error))?;
+        if let Some(source) = &self.source {
+            let origins_path = format!("{}.json", file_path);
+            self.save_origins(&origins_path, source)
+                .map_err(|error| failed(&origins_path, error))?;
+        }
+        Ok(())
+    }
+
+    fn save_png(&self, file_path: &str) -> io::Result<()> {
+        let mut raster = Raster::new(WIDTH as usize, HEIGHT as usize);
+
+        for element in &self.elements {
+            match element {
+                Element::Line(segment, stroke) => raster.line(
+                    (segment.x1, segment.y1),
+                    (segment.x2, segment.y2),
+                    stroke.width,
+                    rgb(&stroke.color),
+                ),
+                Element::Arc(arc, stroke) => {
+                    raster.polyline(&arc.points(), stroke.width, rgb(&stroke.color))
+                }
+                Element::Polygon(points, fill, stroke) => {
+                    raster.polygon(points, rgb(fill));
+                    let mut outline = points.clone();
+                    outline.extend(points.first());
+                    raster.polyline(&outline, stroke.width, rgb(&stroke.color));
+                }
+                Element::Fill((x, y), color) => raster.flood_fill(*x, *y, rgb(color)),
+                Element::Label(label, stroke) => raster.text(
+                    label.position,
+                    &label.text,
+                    label.height,
+                    label.rotation,
+                    rgb(&stroke.color),
+                ),
+            }
+        }
+
+        for &(x, y, angle) in &self.turtles {
+            let (sin, cos) = angle.to_radians().sin_cos();
+            let sprite: Vec<(f64, f64)> = [(10.0, 0.0), (-5.0, 6.0), (-5.0, -6.0)]
+                .iter()
+                .map(|&(px, py)| (x + px * cos - py * sin, y + px * sin + py * cos))
+                .collect();
+            raster.polygon(&sprite, rgb("green"));
+        }
+
+        raster.save(file_path)
+    }
+
+    /// The drawing as SVG text, as saved in a file
+    pub fn svg(&self) -> String {
+        self.document().to_string()
+    }
+
+    fn document(&self) -> Document {
+        let mut group = match &self.animation {
+            Some(animation) => self.animated(animation),
+            None => self.shapes().into_iter().enumerate().fold(
+                Group::new(),
+                |group, (i, (shape, origin))| match shape {
+                    Shape::Outline(outline) => group.add(self.tag(self.path(&outline), i, origin)),
+                    Shape::Label(label, stroke) => {
+                        group.add(self.tag(self.text(label, stroke), i, origin))
+                    }
+                },
+            ),
+        };
+
+        let animated_sprite = self.animation.as_ref().is_some_and(|a| a.sprite);
+        for &(x, y, angle) in self.turtles.iter().filter(|_| !animated_sprite) {
+            group = group.add(self.sprite().set(
+                "transform",
+                format!(
+                    "translate({}) rotate({})",
+                    self.point(x, y),
+                    self.format(angle)
+                ),
+            ));
         }
 
         let square = Rectangle::new()
-            .set("x", -400)
-            .set("y", -400)
-            .set("width", 1200)
-            .set("height", 1200)
+            .set("x", -WIDTH / 2.0)
+            .set("y", -HEIGHT / 2.0)
+            .set("width", WIDTH)
+            .set("height", HEIGHT)
             .set("fill", "white");
 
-        let document = Document::new()
-            .set("viewBox", (-400, -400, 800, 800))
+        Document::new()
+            .set("viewBox", (-WIDTH / 2.0, -HEIGHT / 2.0, WIDTH, HEIGHT))
             .add(square)
-            .add(group);
-
-        svg::save(file_path, &document).expect("Unable to save SVG file");
+            .add(group)
     }
-}
\ No newline at end of file
+}
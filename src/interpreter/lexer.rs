@@ -0,0 +1,97 @@
+/// Characters forming a word of their own
+const DELIMITERS: [u8; 6] = [b'[', b']', b'(', b')', b'*', b'/'];
+
+/// Position after a `~` ending its line, spaces before the newline allowed
+fn continuation(input: &str, tilde: usize) -> Option<usize> {
+    let rest = &input[tilde + 1..];
+    let line_end = rest.find('\n').unwrap_or(rest.len());
+    match rest[..line_end].trim().is_empty() {
+        true => Some(tilde + 1 + (line_end + 1).min(rest.len())),
+        false => None,
+    }
+}
+
+/// End of a comment, which runs to the end of its line and onto the
+/// following ones while they end with `~`
+fn comment_end(input: &str, start: usize) -> usize {
+    let mut end = start;
+    loop {
+        end += input[end..].find('\n').unwrap_or(input.len() - end);
+        match input[start..end].trim_end().ends_with('~') && end < input.len() {
+            true => end += 1,
+            false => return end,
+        }
+    }
+}
+
+/// Splits the source into words, each of them a slice of the input so that
+/// its position can be found back. A comment from `;` to the end of the line
+/// is kept as a single word. Newlines separate words like spaces do, so a `~`
+/// continuing a line only makes a difference to comments.
+pub fn lex(input: &str) -> Vec<&str> {
+    let bytes = input.as_bytes();
+    let mut words = vec![];
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let start = i;
+        match bytes[i] {
+            b';' => i = comment_end(input, i),
+            b'~' if continuation(input, i).is_some() => {
+                i = continuation(input, i).unwrap();
+                continue;
+            }
+            byte if byte.is_ascii_whitespace() => {
+                i += 1;
+                continue;
+            }
+            byte if DELIMITERS.contains(&byte) => i += 1,
+            _ => {
+                while i < bytes.len()
+                    && !bytes[i].is_ascii_whitespace()
+                    && !DELIMITERS.contains(&bytes[i])
+                    && bytes[i] != b';'
+                    && !(bytes[i] == b'~' && continuation(input, i).is_some())
+                {
+                    i += 1;
+                }
+            }
+        }
+        words.push(&input[start..i]);
+    }
+    words
+}
+
+/// Range of bytes of the source covered by some words
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    /// Range of a word given by `lex`, which is a slice of the input
+    pub fn of(input: &str, word: &str) -> Span {
+        let start = word.as_ptr() as usize - input.as_ptr() as usize;
+        Span {
+            start,
+            end: start + word.len(),
+        }
+    }
+
+    /// Range from the start of this span to the end of the other one
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start,
+            end: other.end,
+        }
+    }
+
+    /// Line and column where the span starts, both counted from 1
+    pub fn position(&self, input: &str) -> (usize, usize) {
+        let before = &input[..self.start];
+        let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+        let line = before.matches('\n').count() + 1;
+        (line, before[line_start..].chars().count() + 1)
+    }
+}
@@ -0,0 +1,94 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::interpreter::error::LogoError;
+
+/// Bounds on the work a program may do, `None` leaves that resource unlimited
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    /// Executed instructions, including every iteration of `repeat`
+    pub instructions: Option<u64>,
+    /// Nested procedure calls
+    pub depth: Option<usize>,
+    /// Lines, curves, fills and labels added to the image
+    pub segments: Option<usize>,
+    /// Wall-clock running time
+    pub time: Option<Duration>,
+}
+
+/// The limit that stopped the program
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    Instructions(u64),
+    Depth(usize),
+    Segments(usize),
+    Time(Duration),
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Limit::Instructions(max) => write!(f, "more than {} instructions executed", max),
+            Limit::Depth(max) => write!(f, "procedure calls nested deeper than {}", max),
+            Limit::Segments(max) => write!(f, "more than {} segments drawn", max),
+            Limit::Time(max) => write!(f, "running longer than {:?}", max),
+        }
+    }
+}
+
+/// Resources used so far, checked against the limits
+pub struct Usage {
+    limits: Limits,
+    instructions: u64,
+    depth: usize,
+    started: Instant,
+}
+
+impl Usage {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            instructions: 0,
+            depth: 0,
+            started: Instant::now(),
+        }
+    }
+
+    /// Counts one executed instruction
+    pub fn step(&mut self, segments: usize) -> Result<(), LogoError> {
+        self.instructions += 1;
+
+        let exceeded = match &self.limits {
+            Limits {
+                instructions: Some(max),
+                ..
+            } if self.instructions > *max => Limit::Instructions(*max),
+            Limits {
+                segments: Some(max),
+                ..
+            } if segments > *max => Limit::Segments(*max),
+            Limits {
+                time: Some(max), ..
+            } if self.started.elapsed() > *max => Limit::Time(*max),
+            _ => return Ok(()),
+        };
+        Err(LogoError::LimitExceeded(exceeded))
+    }
+
+    pub fn enter(&mut self) -> Result<(), LogoError> {
+        self.depth += 1;
+        match self.limits.depth {
+            Some(max) if self.depth > max => Err(LogoError::LimitExceeded(Limit::Depth(max))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Procedure calls being run
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn leave(&mut self) {
+        self.depth -= 1;
+    }
+}
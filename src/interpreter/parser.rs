@@ -1,338 +1,735 @@
-use crate::interpreter::parser_types::*;
 use std::collections::HashMap;
 
-const param_prefix: &str = ":";
-const fn_def_prefix: &str = "to";
-const fn_def_suffix: &str = "end";
-
-fn simplify(input: &str) -> String {
-    input
-        .replace("/", " / ")
-        .replace("*", " * ")
-        .replace("[", " [ ")
-        .replace("]", " ] ")
-        .replace("\n", " ")
-    // simplify input, remove all fd, rt etc.
+use crate::interpreter::error::ParseError;
+use crate::interpreter::lexer::{lex, Span};
+use crate::interpreter::parser_types::*;
+
+const PARAM_PREFIX: &str = ":";
+const FN_DEF_PREFIX: &str = "to";
+const FN_DEF_SUFFIX: &str = "end";
+const COMMENT_PREFIX: &str = ";";
+/// Deepest nesting of brackets and operators, beyond which walking the
+/// program could overflow the stack
+const MAX_DEPTH: usize = 100;
+
+/// Words starting an instruction or an expression, which procedures cannot be named after
+pub const PRIMITIVES: [&str; 46] = [
+    "forward",
+    "fd",
+    "back",
+    "bk",
+    "turn",
+    "right",
+    "rt",
+    "left",
+    "lt",
+    "arc",
+    "circle",
+    "ellipse",
+    "repeat",
+    "if",
+    "stop",
+    "wrap",
+    "window",
+    "fence",
+    "setpencolor",
+    "setpc",
+    "setpensize",
+    "setwidth",
+    "tell",
+    "ask",
+    "newturtle",
+    "showturtle",
+    "st",
+    "hideturtle",
+    "ht",
+    "penup",
+    "pu",
+    "pendown",
+    "pd",
+    "setfillcolor",
+    "filled",
+    "fill",
+    "label",
+    "setlabelheight",
+    "setlabelfont",
+    "who",
+    "count",
+    "item",
+    FN_DEF_PREFIX,
+    FN_DEF_SUFFIX,
+    "(",
+    ")",
+];
+
+/// Parses a program into its top-level commands and its procedures, failing
+/// with the first mistake in the source
+pub fn parse(input: &str) -> Result<(Vec<Token>, Functions), ParseError> {
+    let (commands, fns, mut errors) = parse_all(input);
+    match errors.is_empty() {
+        true => Ok((commands, fns)),
+        false => Err(errors.remove(0)),
+    }
 }
 
-pub fn parse(input: &str) -> (Vec<Token>, Functions) {
-    let mut commands = vec![];
-    let mut fns = Functions::new();
-
-    let mut labels: Vec<String> = vec![];
-    let mut label_arity: HashMap<String, usize> = HashMap::new(); // function arity?
-
-    let input = simplify(input);
-    input.split(fn_def_suffix).into_iter().for_each(|block| {
-        let tokens: Vec<&str> = block.split_whitespace().collect();
-        if block.starts_with(fn_def_prefix) {
-            fns.push(parse_fn(&tokens[..], &mut labels, &mut label_arity));
-        } else {
-            let (mut tokens, _) = parse_tokens(&tokens[..], &labels);
-            commands.append(&mut tokens);
+/// Parses as much of a program as possible, collecting its mistakes in the
+/// order they appear. Parsing carries on after the ones that leave the
+/// structure of the program clear, like calls to undefined procedures.
+pub fn parse_all(input: &str) -> (Vec<Token>, Functions, Vec<ParseError>) {
+    let mut parser = Parser::new(input);
+    let commands = match parser.program() {
+        Ok(commands) => commands,
+        Err(error) => {
+            parser.errors.push(error);
+            vec![]
         }
-    });
+    };
+    parser.errors.sort_by_key(|error| error.span.start);
+    (commands, parser.fns, parser.errors)
+}
 
-    let wrapped_commands = wrap_fn_call(commands, &fns);
-    //println!("Finished wrapping commands. Result is {:?}", wrapped_commands);
-    (wrapped_commands, fns)
+struct Parser<'a> {
+    input: &'a str,
+    words: Vec<&'a str>,
+    /// Position of the next word
+    i: usize,
+    /// Position of the last word that was read
+    last: usize,
+    /// Procedures with their inputs, all collected before parsing any body so
+    /// that procedures can be called before their definition and call each other
+    fns: Functions,
+    /// Name, start of the body and comments within the header of the
+    /// definition found at each position
+    definitions: HashMap<usize, (String, usize, Vec<Token>)>,
+    /// Procedure whose body is being parsed, with the inputs it may refer to
+    procedure: Option<String>,
+    params: Vec<String>,
+    rest: Option<String>,
+    /// Comments read in the middle of an instruction, which follow it
+    comments: Vec<Token>,
+    /// Brackets and operators around the word being parsed
+    depth: usize,
+    /// Mistakes after which parsing went on
+    errors: Vec<ParseError>,
 }
 
-pub fn wrap_fn_call(tokens: Vec<Token>, fns: &Functions) -> Vec<Token> {
-    /// Pairs all of the function labels in the input with their arguments
-    let mut wrapped_tokens = Vec::new();
-    let mut iter = tokens.into_iter();
-
-    while let Some(token) = iter.next() {
-        //println!("Wrap function call checking token {:?}", token);
-        match token {
-            Token::FnLabel(label) => {
-                //println!("Wrapping function call to {}", label);
-                if let Some(fun) = fns.get(&label) {
-                    let arity = fun.arity();
-                    let mut args = Vec::new();
-
-                    for _ in 0..arity {
-                        if let Some(Token::Expression(expr)) = iter.next() {
-                            args.push(*expr);
-                        } else {
-                            panic!(
-                                "Function '{}' expected {} arguments but fewer were provided",
-                                label, arity
-                            );
-                        }
-                    }
+impl<'a> Parser<'a> {
+    fn new(input: &'a str) -> Self {
+        Self {
+            input,
+            words: lex(input),
+            i: 0,
+            last: 0,
+            fns: Functions::new(),
+            definitions: HashMap::new(),
+            procedure: None,
+            params: vec![],
+            rest: None,
+            comments: vec![],
+            depth: 0,
+            errors: vec![],
+        }
+    }
 
-                    wrapped_tokens.push(Token::FnCall(label, args));
-                } else {
-                    panic!("Function '{}' not found in the environment", label);
-                }
+    fn span(&self, from: usize, to: usize) -> Span {
+        Span::of(self.input, self.words[from]).to(Span::of(self.input, self.words[to]))
+    }
+
+    fn error<T>(&self, span: Span, message: String) -> Result<T, ParseError> {
+        Err(ParseError { message, span })
+    }
+
+    /// Goes one level deeper into the program for the word at that position
+    fn nest(&mut self, at: usize) -> Result<(), ParseError> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return self.error(
+                self.span(at, at),
+                format!("Nested more than {} deep", MAX_DEPTH),
+            );
+        }
+        Ok(())
+    }
+
+    /// Records a mistake that does not stop the parsing
+    fn report(&mut self, span: Span, message: String) {
+        self.errors.push(ParseError { message, span });
+    }
+
+    /// Next word that is not a comment, the comments skipped following the
+    /// instruction being parsed
+    fn peek(&mut self) -> Option<&'a str> {
+        while let Some(&word) = self.words.get(self.i) {
+            if !word.starts_with(COMMENT_PREFIX) {
+                return Some(word);
             }
-            Token::Repeat(iterations, block) => {
-                if let Token::Bracket(bracket) = *block {
-                    let wrapped_block = wrap_fn_call(bracket, fns);
-                    wrapped_tokens.push(Token::Repeat(
-                        iterations,
-                        Box::new(Token::Bracket(wrapped_block)),
-                    ));
-                } else {
-                    panic!("Repeating token that is not a bracket");
-                }
+            let span = Span::of(self.input, word);
+            self.comments
+                .push(Token::Comment(word[1..].to_string(), span));
+            self.i += 1;
+        }
+        None
+    }
+
+    /// Reads the next word, which should be what `expected` describes
+    fn next(&mut self, expected: &str) -> Result<&'a str, ParseError> {
+        match self.peek() {
+            Some(word) => {
+                self.last = self.i;
+                self.i += 1;
+                Ok(word)
             }
-            Token::If(log_expr, block) => {
-                if let Token::Bracket(bracket) = *block {
-                    let wrapped_body = wrap_fn_call(bracket, fns);
-                    wrapped_tokens
-                        .push(Token::If(log_expr, Box::new(Token::Bracket(wrapped_body))));
-                } else {
-                    panic!("Conditionally executed token that is not a bracket");
-                }
+            None => {
+                let end = Span {
+                    start: self.input.len(),
+                    end: self.input.len(),
+                };
+                self.error(end, format!("Expected {} but the program ended", expected))
             }
-            other => wrapped_tokens.push(other),
         }
     }
 
-    wrapped_tokens
-}
+    /// Reads an opening bracket, returning its position
+    fn open(&mut self) -> Result<usize, ParseError> {
+        match self.next("'['")? {
+            "[" => Ok(self.last),
+            other => self.error(
+                self.span(self.last, self.last),
+                format!("Expected '[' but found '{}'", other),
+            ),
+        }
+    }
 
-fn parse_fn(
-    tokens: &[&str],
-    labels: &mut Vec<String>,
-    label_arity: &mut HashMap<String, usize>,
-) -> (String, Vec<Token>, Vec<String>) {
-    //println!("Parsing function body {:?}", tokens);
-    if tokens[0] != "to" {
-        panic!();
+    fn program(&mut self) -> Result<Vec<Token>, ParseError> {
+        self.definitions()?;
+
+        let mut commands = vec![];
+        while let Some(&word) = self.words.get(self.i) {
+            match word {
+                FN_DEF_PREFIX => self.parse_fn()?,
+                FN_DEF_SUFFIX | "]" => {
+                    let span = self.span(self.i, self.i);
+                    return self.error(span, format!("Unexpected '{}'", word));
+                }
+                _ => self.parse_token(&mut commands)?,
+            }
+        }
+        Ok(commands)
     }
 
-    let mut params: Vec<String>;
-    let mut fn_body_start = 2;
-    if tokens[2].starts_with(param_prefix) {
-        print!("Parsing function, found parameters");
-        let param_start: usize = 2;
-        fn_body_start = tokens[param_start..]
-            .iter()
-            .position(|token| !token.starts_with(param_prefix))
-            .unwrap()
-            + param_start;
+    /// Collects the name and inputs of every definition that is not inside brackets
+    fn definitions(&mut self) -> Result<(), ParseError> {
+        let mut depth = 0;
+        while let Some(&word) = self.words.get(self.i) {
+            if let Some(close) = self.list_end(self.i) {
+                self.i = close + 1;
+                continue;
+            }
+            match word {
+                "[" => depth += 1,
+                "]" => depth -= 1,
+                FN_DEF_PREFIX if depth == 0 => {
+                    let start = self.i;
+                    let (label, fun) = self.parse_header()?;
+                    let comments = self.comments.drain(..).collect();
+                    self.definitions
+                        .insert(start, (label.clone(), self.i, comments));
+                    if self.fns.contains(&label) {
+                        self.report(fun.span, format!("'{}' is defined twice", label));
+                    } else {
+                        self.fns.push(label, fun);
+                    }
+                    // The body runs up to the next `end` that is not in a list
+                    while self
+                        .words
+                        .get(self.i)
+                        .is_some_and(|&word| word != FN_DEF_SUFFIX)
+                    {
+                        self.i = self.list_end(self.i).unwrap_or(self.i) + 1;
+                    }
+                }
+                _ => {}
+            }
+            self.i += 1;
+        }
+        self.i = 0;
+        self.comments.clear();
+        Ok(())
+    }
 
-        params = tokens[param_start..fn_body_start]
+    /// Position of the `]` closing the list given to the `label` or
+    /// `setlabelfont` at that position, whose words are not instructions
+    fn list_end(&self, i: usize) -> Option<usize> {
+        if !["label", "setlabelfont"].contains(&self.words[i]) {
+            return None;
+        }
+        let comments = self.words[i + 1..]
             .iter()
-            .map(|&token| String::from(token))
-            .collect();
-    } else {
-        //println!("Parsing function, no parameters found");
-        params = vec![];
+            .take_while(|word| word.starts_with(COMMENT_PREFIX))
+            .count();
+        let open = i + 1 + comments;
+        if self.words.get(open) != Some(&"[") {
+            return None;
+        }
+        let close = self.words[open..].iter().position(|&word| word == "]")?;
+        Some(open + close)
     }
 
-    let label = tokens[1];
-    labels.push(String::from(label));
-    label_arity.insert(String::from(label), params.len());
+    /// Parses `to`, the name and the inputs of a definition: `:required`,
+    /// `[:optional default]` and a final `[:rest]`
+    fn parse_header(&mut self) -> Result<(String, Fun), ParseError> {
+        let start = self.i;
+        self.i += 1;
+        let label = self.next("a procedure name after 'to'")?;
+        let valid = label.starts_with(|c: char| c.is_alphabetic());
+        if !valid || PRIMITIVES.contains(&label) {
+            return self.error(
+                self.span(self.last, self.last),
+                format!("'{}' cannot be the name of a procedure", label),
+            );
+        }
 
-    //println!("Parsing function body from token {}, found {} params", fn_body_start, params.len());
-    let (fn_body, _) = parse_tokens(&tokens[fn_body_start..], &labels);
-    //println!("Function body is {:#?}", fn_body);
-    (String::from(label), fn_body, params)
-}
+        let mut fun = Fun::new(vec![], vec![]);
+        // Comments after the inputs belong to the body, so they are not skipped
+        while let Some(&word) = self.words.get(self.i) {
+            let next = self.words.get(self.i + 1);
+            if word.starts_with(PARAM_PREFIX) {
+                if !fun.defaults.is_empty() || fun.rest.is_some() {
+                    return self.error(
+                        self.span(self.i, self.i),
+                        format!("Required input {} of '{}' after optional ones", word, label),
+                    );
+                }
+                fun.params.push(String::from(word));
+                self.i += 1;
+            } else if word == "[" && next.is_some_and(|next| next.starts_with(PARAM_PREFIX)) {
+                let param = String::from(self.words[self.i + 1]);
+                self.i += 2;
+                if fun.rest.is_some() {
+                    return self.error(
+                        self.span(self.i - 1, self.i - 1),
+                        format!("Input {} of '{}' after the rest input", param, label),
+                    );
+                }
+                if self.words.get(self.i) == Some(&"]") {
+                    fun.rest = Some(param);
+                } else {
+                    // Defaults may refer to the inputs before them
+                    self.enter(label, fun.params.clone(), None);
+                    let default = self.parse_expr();
+                    self.procedure = None;
+                    fun.defaults.push(default?);
+                    fun.params.push(param);
+                    if self.peek() != Some("]") {
+                        return self.error(
+                            self.span(start, self.last),
+                            format!("Default value of {} must be a single expression", label),
+                        );
+                    }
+                }
+                self.i += 1;
+            } else {
+                break;
+            }
+        }
+        fun.span = self.span(start, self.i - 1);
+        Ok((String::from(label), fun))
+    }
 
-pub fn parse_tokens(input: &[&str], labels: &Vec<String>) -> (Vec<Token>, usize) {
-    let mut tokens = Vec::new();
-    let mut i = 0;
+    /// Brings the inputs of a procedure in scope
+    fn enter(&mut self, label: &str, params: Vec<String>, rest: Option<String>) {
+        self.procedure = Some(String::from(label));
+        self.params = params;
+        self.rest = rest;
+    }
 
-    while i < input.len() {
-        //println!("parsing text-token {} at index {} of {:?}", input[i], i, input);
-        match input[i] {
-            "stop" => {
-                i += 1;
-                tokens.push(Token::Stop);
+    /// Parses a definition, whose inputs were read by `definitions`
+    fn parse_fn(&mut self) -> Result<(), ParseError> {
+        let Some((label, body_start, comments)) = self.definitions.get(&self.i).cloned() else {
+            let span = self.span(self.i, self.i);
+            return self.error(span, format!("Unexpected '{}'", FN_DEF_PREFIX));
+        };
+        self.i = body_start;
+        let fun = self.fns.get(&label).unwrap();
+        self.enter(&label, fun.params.clone(), fun.rest.clone());
+
+        // Comments within the header come first, following it like any other
+        let mut body = comments;
+        // Without an `end`, the definition runs to the end of the program
+        let mut end = self.span(self.words.len() - 1, self.words.len() - 1);
+        while let Some(&word) = self.words.get(self.i) {
+            match word {
+                FN_DEF_SUFFIX => {
+                    end = self.span(self.i, self.i);
+                    self.i += 1;
+                    break;
+                }
+                FN_DEF_PREFIX | "]" => {
+                    let span = self.span(self.i, self.i);
+                    let message = match word {
+                        "]" => format!("Unexpected '{}'", word),
+                        _ => format!("Definition inside '{}', which is missing its 'end'", label),
+                    };
+                    return self.error(span, message);
+                }
+                _ => self.parse_token(&mut body)?,
             }
-            "forward" => {
-                i += 1;
-                let expr = parse_expr(input, &mut i);
-                //println!("Parsed expr is {:?}", expr);
-                tokens.push(Token::Forward(Box::new(expr)));
+        }
+
+        self.procedure = None;
+        let fun = self.fns.functions.get_mut(&label).unwrap();
+        if fun.body.is_empty() {
+            fun.body = body;
+            fun.end = end;
+        }
+        Ok(())
+    }
+
+    /// Parses the instruction or the comment at the current position. Each
+    /// instruction is preceded by its span in the source.
+    fn parse_token(&mut self, tokens: &mut Vec<Token>) -> Result<(), ParseError> {
+        let word = self.words[self.i];
+        if let Some(text) = word.strip_prefix(COMMENT_PREFIX) {
+            self.i += 1;
+            tokens.push(Token::Comment(text.to_string(), Span::of(self.input, word)));
+            return Ok(());
+        }
+
+        let start = self.i;
+        let comments = self.comments.len();
+        let token = self.parse_instruction()?;
+        tokens.push(Token::Source(self.span(start, self.last)));
+        tokens.push(token);
+        tokens.extend(self.comments.drain(comments..));
+        Ok(())
+    }
+
+    fn parse_instruction(&mut self) -> Result<Token, ParseError> {
+        let start = self.i;
+        let token = match self.next("an instruction")? {
+            "stop" => Token::Stop,
+            "wrap" => Token::Wrap,
+            "window" => Token::Window,
+            "fence" => Token::Fence,
+            "showturtle" | "st" => Token::ShowTurtle,
+            "hideturtle" | "ht" => Token::HideTurtle,
+            "newturtle" => Token::NewTurtle,
+            "penup" | "pu" => Token::PenUp,
+            "pendown" | "pd" => Token::PenDown,
+            "fill" => Token::Fill,
+            "setpencolor" | "setpc" => Token::SetPenColor(self.parse_color()?),
+            "setfillcolor" => Token::SetFillColor(self.parse_color()?),
+            "setpensize" | "setwidth" => Token::SetPenSize(Box::new(self.parse_expr()?)),
+            "setlabelheight" => Token::SetLabelHeight(Box::new(self.parse_expr()?)),
+            "forward" | "fd" => Token::Forward(Box::new(self.parse_expr()?)),
+            "back" | "bk" => Token::Back(Box::new(self.parse_expr()?)),
+            "turn" | "right" | "rt" => Token::TurnRight(Box::new(self.parse_expr()?)),
+            "left" | "lt" => Token::TurnLeft(Box::new(self.parse_expr()?)),
+            "circle" => Token::Circle(Box::new(self.parse_expr()?)),
+            "arc" => {
+                let angle = self.parse_expr()?;
+                let radius = self.parse_expr()?;
+                Token::Arc(Box::new(angle), Box::new(radius))
             }
-            "back" => {
-                i += 1;
-                let expr = parse_expr(input, &mut i);
-                //println!("Parsed expr is {:?}", expr);
-                tokens.push(Token::Back(Box::new(expr)));
+            "ellipse" => {
+                let width = self.parse_expr()?;
+                let height = self.parse_expr()?;
+                Token::Ellipse(Box::new(width), Box::new(height))
             }
-            "turn" | "right" => {
-                i += 1;
-                //println!("Start parsing turn");
-                let expr = parse_expr(input, &mut i);
-                //println!("End parsing turn");
-                //println!("Parsed expr is {:?}", expr);
-                tokens.push(Token::TurnRight(Box::new(expr)));
+            "label" => Token::Label(self.parse_word()?),
+            "setlabelfont" => Token::SetLabelFont(self.parse_word()?),
+            "tell" => Token::Tell(self.parse_turtle_ids()?),
+            "ask" => {
+                let ids = self.parse_turtle_ids()?;
+                let open = self.open()?;
+                Token::Ask(ids, Box::new(self.parse_bracket(open)?))
             }
-
-            "left" => {
-                i += 1;
-                //println!("Start parsing turn");
-                let expr = parse_expr(input, &mut i);
-                //println!("End parsing turn");
-                //println!("Parsed expr is {:?}", expr);
-                tokens.push(Token::TurnLeft(Box::new(expr)));
+            "filled" => {
+                let color = self.parse_color()?;
+                let open = self.open()?;
+                Token::Filled(color, Box::new(self.parse_bracket(open)?))
             }
             "repeat" => {
-                //println!("Parsing repeat starting at {:?}", input);
-                i += 1;
-                let expr = parse_expr(input, &mut i);
-                //println!("Bracket parsing - start");
-                i += 1;
-                let bracket = parse_bracket(input, &mut i, labels);
-                //println!("Bracket parsing - end");
-                //println!("Parsed expr is {:?}", expr);
-                //println!("Parsed bracket is {:?}", bracket);
-                tokens.push(Token::Repeat(Box::new(expr), Box::new(bracket)));
+                let expr = self.parse_expr()?;
+                let open = self.open()?;
+                Token::Repeat(Box::new(expr), Box::new(self.parse_bracket(open)?))
             }
             "if" => {
-                i += 1;
-                let expr1 = parse_expr(input, &mut i);
-                let log_op = input[i];
-                i += 1;
-                let expr2 = parse_expr(input, &mut i);
-                i += 1;
-                let body = parse_bracket(input, &mut i, labels);
-                //println!("If statement body is {:#?}", body);
-
-                match log_op {
-                    ">" => {
-                        tokens.push(Token::If(
-                            LogExpr::Greater(Box::new(expr1), Box::new(expr2)),
-                            Box::new(body),
-                        ));
-                    }
-                    "<" => {
-                        tokens.push(Token::If(
-                            LogExpr::Less(Box::new(expr1), Box::new(expr2)),
-                            Box::new(body),
-                        ));
-                    }
+                let lhs = Box::new(self.parse_expr()?);
+                let log_op = self.next("'<' or '>'")?;
+                let rhs = Box::new(self.parse_expr()?);
+                let log_expr = match log_op {
+                    ">" => LogExpr::Greater(lhs, rhs),
+                    "<" => LogExpr::Less(lhs, rhs),
                     other => {
-                        panic!("Logical expression with invalid operator {}", other);
+                        return self.error(
+                            self.span(start, self.last),
+                            format!("Logical expression with invalid operator {}", other),
+                        );
+                    }
+                };
+                let open = self.open()?;
+                Token::If(log_expr, Box::new(self.parse_bracket(open)?))
+            }
+            "[" => self.parse_bracket(start)?,
+            // Parenthesised call giving any number of inputs
+            "(" => {
+                let label = self.next("a procedure name after '('")?;
+                let mut args = vec![];
+                loop {
+                    match self.peek() {
+                        Some(")") => break,
+                        Some(_) => args.push(self.parse_expr()?),
+                        None => {
+                            return self.error(
+                                self.span(start, start),
+                                String::from("'(' is never closed"),
+                            );
+                        }
                     }
                 }
+                self.next("')'")?;
+                let span = self.span(start, self.last);
+                match self.fns.get(&String::from(label)) {
+                    Some(fun) if !fun.accepts(args.len()) => self.report(
+                        span,
+                        format!("Function '{}' cannot take {} arguments", label, args.len()),
+                    ),
+                    Some(_) => {}
+                    None => self.report(span, format!("Function '{}' is not defined", label)),
+                }
+                Token::FnCall(label.to_string(), args)
             }
-            "]" => {
-                //println!("Finished parsing as bracket is closed, returning");
-                return (tokens, i - 1);
+            label if self.fns.contains(&String::from(label)) => {
+                let arity = self.fns.get(&String::from(label)).unwrap().arity();
+                let mut args = vec![];
+                while args.len() < arity && self.starts_expr() {
+                    args.push(self.parse_expr()?);
+                }
+                if args.len() < arity {
+                    self.report(
+                        self.span(start, self.last),
+                        format!(
+                            "Function '{}' expected {} arguments but {} were provided",
+                            label,
+                            arity,
+                            args.len()
+                        ),
+                    );
+                }
+                Token::FnCall(label.to_string(), args)
             }
-
-            token if labels.contains(&String::from(token)) => {
-                i += 1;
-                tokens.push(Token::FnLabel(token.to_string()));
+            _ if self.starts_value(start) => {
+                self.i = start;
+                let expr = self.parse_expr()?;
+                self.report(
+                    self.span(start, self.last),
+                    String::from("Value given to no instruction"),
+                );
+                Token::Expression(Box::new(expr))
             }
-            token => {
-                //println!("Start expr wrapping at text token {}", token);
-                let expr = parse_expr(input, &mut i); //debug
-                                                      //println!("Parsed expr is {:?}", expr);
-                tokens.push(Token::Expression(Box::new(expr)));
+            label => {
+                // Reading the inputs that follow keeps them from being reported too
+                let mut args = vec![];
+                while self.starts_expr() {
+                    args.push(self.parse_expr()?);
+                }
+                self.report(
+                    self.span(start, start),
+                    format!("Function '{}' is not defined", label),
+                );
+                Token::FnCall(label.to_string(), args)
             }
-        }
-        //println!("Finished parsing token at index {:?} next index is {}", debug_val, i)
+        };
+        Ok(token)
     }
-    (tokens, i)
-}
 
-fn parse_expr(input: &[&str], i: &mut usize) -> Expr {
-    println!("Parsing {:?} at index {}", input, *i);
-    let mut expr_stack = Vec::new();
-    let mut op_stack = Vec::new();
-
-    let mut read_next = "variable";
+    /// Whether the word at that position starts an expression
+    fn starts_value(&self, i: usize) -> bool {
+        match self.words.get(i) {
+            Some(&word) => {
+                word.starts_with(PARAM_PREFIX)
+                    || ["who", "count", "item"].contains(&word)
+                    || word.parse::<f64>().is_ok()
+            }
+            None => false,
+        }
+    }
 
-    let mut expr_finished = false;
-    while !expr_finished && *i < input.len() {
-        println!("Parse expr matching token {}", input[*i]);
+    /// Whether the next word that is not a comment starts an expression
+    fn starts_expr(&mut self) -> bool {
+        self.peek().is_some() && self.starts_value(self.i)
+    }
 
-        match read_next {
-            "operator" => match input[*i] {
-                "*" | "/" | "+" | "-" => {
-                    op_stack.push(input[*i]);
-                    *i += 1;
-                    read_next = "variable";
+    /// Instructions up to the closing bracket, the opening one at `open`
+    /// having been read
+    fn parse_bracket(&mut self, open: usize) -> Result<Token, ParseError> {
+        self.nest(open)?;
+        let mut contents = vec![];
+        loop {
+            match self.words.get(self.i) {
+                Some(&"]") => {
+                    self.last = self.i;
+                    self.i += 1;
+                    self.depth -= 1;
+                    return Ok(Token::Bracket(contents));
                 }
-                other => {
-                    println!(
-                        "Parser found {} instead of operator, finishing expression",
-                        other
-                    );
-                    expr_finished = true;
-                    break;
+                Some(&FN_DEF_PREFIX) | Some(&FN_DEF_SUFFIX) | None => {
+                    return self.error(self.span(open, open), String::from("'[' is never closed"));
                 }
-            },
-            "variable" => {
-                match input[*i] {
-                    token if token.starts_with(':') => {
-                        expr_stack.push(Expr::Arg(Arg::Param(token.to_string())));
-                        *i += 1;
-                    }
-                    token => {
-                        if let Ok(num) = token.parse::<f64>() {
-                            expr_stack.push(Expr::Arg(Arg::Val(num)));
-                            *i += 1;
-                        //println!("Pushed value {} to stack", num);
-                        } else {
-                            panic!("Parser found {} instead of variable", token);
-                        }
-                    }
+                Some(_) => self.parse_token(&mut contents)?,
+            }
+        }
+    }
+
+    /// Parses values joined by operators, all of them having the same
+    /// precedence and grouping from the right
+    fn parse_expr(&mut self) -> Result<Expr, ParseError> {
+        let depth = self.depth;
+        let mut expr_stack = vec![self.parse_value()?];
+        let mut op_stack = vec![];
+
+        while let Some(op @ ("*" | "/" | "+" | "-")) = self.peek() {
+            self.next("an operator")?;
+            // Each operator nests the values after it one level deeper
+            self.nest(self.last)?;
+            op_stack.push(op);
+            expr_stack.push(self.parse_value()?);
+        }
+        self.depth = depth;
+
+        let mut expr = expr_stack.pop().unwrap();
+        while let (Some(op), Some(lhs)) = (op_stack.pop(), expr_stack.pop()) {
+            let (lhs, rhs) = (Box::new(lhs), Box::new(expr));
+            expr = match op {
+                "*" => Expr::Mul(lhs, rhs),
+                "/" => Expr::Div(lhs, rhs),
+                "+" => Expr::Add(lhs, rhs),
+                _ => Expr::Sub(lhs, rhs),
+            };
+        }
+        Ok(expr)
+    }
+
+    fn parse_value(&mut self) -> Result<Expr, ParseError> {
+        let word = self.next("a value")?;
+        let span = self.span(self.last, self.last);
+        match word {
+            param if param.starts_with(PARAM_PREFIX) => {
+                match self.input_kind(param) {
+                    Some(false) => {}
+                    Some(true) => self.report(
+                        span,
+                        format!("Rest input {} can only be given to count or item", param),
+                    ),
+                    None => self.undeclared(param, span),
                 }
-                read_next = "operator";
+                Ok(Expr::Arg(Arg::Param(param.to_string())))
             }
-            other => {
-                panic! {"Illegal value of read_next {}", other};
+            "who" => Ok(Expr::Arg(Arg::Who)),
+            "count" => Ok(Expr::Count(self.parse_list()?)),
+            "item" => {
+                self.nest(self.last)?;
+                let index = self.parse_expr()?;
+                self.depth -= 1;
+                Ok(Expr::Item(Box::new(index), self.parse_list()?))
             }
+            word => match word.parse::<f64>() {
+                Ok(num) => Ok(Expr::Arg(Arg::Val(num))),
+                Err(_) => self.error(span, format!("Expected a value but found '{}'", word)),
+            },
         }
     }
 
-    //println!("Popping operators from stack");
-    while let Some(op) = op_stack.pop() {
-        let rhs = expr_stack.pop().unwrap();
-        let lhs = expr_stack.pop().unwrap();
-        expr_stack.push(match op {
-            "*" => Expr::Mul(Box::new(lhs), Box::new(rhs)),
-            "/" => Expr::Div(Box::new(lhs), Box::new(rhs)),
-            "+" => Expr::Add(Box::new(lhs), Box::new(rhs)),
-            "-" => Expr::Sub(Box::new(lhs), Box::new(rhs)),
-            _ => unreachable!(),
-        });
+    /// Whether an input of the current procedure is its rest input, `None`
+    /// when there is no such input
+    fn input_kind(&self, param: &str) -> Option<bool> {
+        self.procedure.as_ref()?;
+        match self.params.iter().any(|name| name == param) {
+            true => Some(false),
+            false => self
+                .rest
+                .as_ref()
+                .filter(|&rest| rest == param)
+                .map(|_| true),
+        }
     }
 
-    println!("Finished parsing single expression");
-    expr_stack.pop().unwrap()
-}
-
-fn parse_bracket(input: &[&str], i: &mut usize, labels: &Vec<String>) -> Token {
-    let mut contents = Vec::new();
+    fn undeclared(&mut self, param: &str, span: Span) {
+        let message = match &self.procedure {
+            Some(label) => format!("Parameter '{}' is not an input of '{}'", param, label),
+            None => format!("Parameter '{}' used outside of a procedure", param),
+        };
+        self.report(span, message);
+    }
 
-    while *i < input.len() {
-        match input[*i] {
-            "]" => {
-                *i += 1;
-                break;
-            }
-            "[" => {
-                *i += 1;
-                contents.push(parse_bracket(input, i, labels));
-            }
+    /// Rest input given to `count` or `item`
+    fn parse_list(&mut self) -> Result<List, ParseError> {
+        let word = self.next("a rest input")?;
+        let span = self.span(self.last, self.last);
+        if !word.starts_with(PARAM_PREFIX) {
+            return self.error(span, format!("Expected a rest input but found '{}'", word));
+        }
+        match self.input_kind(word) {
+            Some(true) => {}
+            Some(false) => self.report(span, format!("Input {} is not a rest input", word)),
+            None => self.undeclared(word, span),
+        }
+        Ok(List::Param(word.to_string()))
+    }
 
-            _token => {
-                let (tokens, num_processed_text_tokens) = parse_tokens(&input[*i..], labels);
-                contents.extend(tokens);
-                *i += num_processed_text_tokens;
+    fn parse_color(&mut self) -> Result<Color, ParseError> {
+        match self.peek().and_then(|word| word.strip_prefix('"')) {
+            Some(name) => {
+                self.next("a color")?;
+                Ok(Color::Name(name.to_string()))
             }
+            None => Ok(Color::Index(Box::new(self.parse_expr()?))),
         }
-        *i += 1;
     }
 
-    if *i < input.len() && input[*i - 1] != "]" {
-        panic!(
-            "Unmatched '[' in input {:?}, position {}, instead {}",
-            input, *i, input[*i]
-        );
+    /// Single turtle number or a list of them in brackets
+    fn parse_turtle_ids(&mut self) -> Result<Vec<Expr>, ParseError> {
+        if self.peek() != Some("[") {
+            return Ok(vec![self.parse_expr()?]);
+        }
+        self.next("'['")?;
+        let mut ids = vec![];
+        while self.peek() != Some("]") {
+            ids.push(self.parse_expr()?);
+        }
+        self.next("']'")?;
+        Ok(ids)
     }
 
-    Token::Bracket(contents)
+    fn parse_word(&mut self) -> Result<Word, ParseError> {
+        if self.peek() == Some("[") {
+            let open = self.i;
+            let close = self.words[open..].iter().position(|&word| word == "]");
+            let Some(close) = close.map(|position| open + position) else {
+                return self.error(self.span(open, open), String::from("'[' is never closed"));
+            };
+            // Comments in the list follow the instruction like any other
+            let mut words = vec![];
+            for &word in &self.words[open + 1..close] {
+                match word.strip_prefix(COMMENT_PREFIX) {
+                    Some(text) => self
+                        .comments
+                        .push(Token::Comment(text.to_string(), Span::of(self.input, word))),
+                    None => words.push(word),
+                }
+            }
+            let words = words.join(" ");
+            self.last = close;
+            self.i = close + 1;
+            return Ok(Word::Literal(words));
+        }
+        match self.peek().and_then(|word| word.strip_prefix('"')) {
+            Some(word) => {
+                self.next("a word")?;
+                Ok(Word::Literal(word.to_string()))
+            }
+            None => Ok(Word::Number(Box::new(self.parse_expr()?))),
+        }
+    }
 }
@@ -1,12 +1,33 @@
 use std::collections::HashMap;
 
-use crate::interpreter::parser::wrap_fn_call;
+use crate::interpreter::lexer::Span;
 use crate::interpreter::{substitute_token, substitute_expr};
 
+/// Colors of the jslogo palette, indexed by `setpencolor`/`setfillcolor` numbers
+pub const PALETTE: [&str; 16] = [
+    "black",
+    "blue",
+    "lime",
+    "cyan",
+    "red",
+    "magenta",
+    "yellow",
+    "white",
+    "brown",
+    "tan",
+    "green",
+    "aquamarine",
+    "salmon",
+    "purple",
+    "orange",
+    "gray",
+];
+
 #[derive(Debug, Clone)]
 pub enum Arg {
     Val(f64),
     Param(String),
+    Who, // number of the turtle evaluating the expression
 }
 
 #[derive(Debug, Clone)]
@@ -17,15 +38,15 @@ pub enum LogExpr {
 }
 
 impl LogExpr {
-    pub fn evaluate(&self) -> LogExpr {
+    pub fn evaluate(&self, who: usize) -> LogExpr {
         match self {
-            LogExpr::Greater(lhs, rhs) => LogExpr::Val(lhs.evaluate() > rhs.evaluate()),
-            LogExpr::Less(lhs, rhs) => LogExpr::Val(lhs.evaluate() < rhs.evaluate()),
+            LogExpr::Greater(lhs, rhs) => LogExpr::Val(lhs.evaluate(who) > rhs.evaluate(who)),
+            LogExpr::Less(lhs, rhs) => LogExpr::Val(lhs.evaluate(who) < rhs.evaluate(who)),
             LogExpr::Val(_) => {self.clone()},
         }
     }
 
-    pub fn substitute(&self, param_evaluator: &HashMap<String, f64>) -> LogExpr {
+    pub fn substitute(&self, param_evaluator: &Bindings) -> LogExpr {
         match self {
             LogExpr::Greater(lhs, rhs) => LogExpr::Greater(
                 substitute_expr(lhs, param_evaluator) , 
@@ -38,24 +59,119 @@ impl LogExpr {
     }
 }
 
+#[derive(Debug, Clone)]
+pub enum Color {
+    Name(String),        // "red
+    Index(Box<Expr>),    // palette number
+}
+
+impl Color {
+    /// Resolves to a color usable in SVG
+    pub fn evaluate(&self, who: usize) -> String {
+        match self {
+            Color::Name(name) => name.clone(),
+            Color::Index(expr) => {
+                String::from(PALETTE[expr.evaluate(who).max(0.0) as usize % PALETTE.len()])
+            }
+        }
+    }
+
+    pub fn substitute(&self, param_evaluator: &Bindings) -> Color {
+        match self {
+            Color::Name(_) => self.clone(),
+            Color::Index(expr) => Color::Index(substitute_expr(expr, param_evaluator)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Word {
+    Literal(String),    // "word or [list of words]
+    Number(Box<Expr>),
+}
+
+impl Word {
+    pub fn evaluate(&self, who: usize) -> String {
+        match self {
+            Word::Literal(text) => text.clone(),
+            Word::Number(expr) => expr.evaluate(who).to_string(),
+        }
+    }
+
+    pub fn substitute(&self, param_evaluator: &Bindings) -> Word {
+        match self {
+            Word::Literal(_) => self.clone(),
+            Word::Number(expr) => Word::Number(substitute_expr(expr, param_evaluator)),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Token {
     Forward(Box<Expr>),
     Back(Box<Expr>),
     TurnRight(Box<Expr>),
     TurnLeft(Box<Expr>),
+    Arc(Box<Expr>, Box<Expr>), // angle, radius
+    Circle(Box<Expr>),
+    Ellipse(Box<Expr>, Box<Expr>),
     Repeat(Box<Expr>, Box<Token>),
-    FnLabel(String), // execution should panic
     FnCall(String, Vec<Expr>),
     Bracket(Vec<Token>),
     If(LogExpr, Box<Token>),
     Expression(Box<Expr>),
+    Wrap,
+    Window,
+    Fence,
+    SetPenColor(Color),
+    SetPenSize(Box<Expr>),
+    Tell(Vec<Expr>),             // turtles receiving the following commands
+    Ask(Vec<Expr>, Box<Token>),  // turtles running only the block
+    NewTurtle,
+    ShowTurtle,
+    HideTurtle,
+    PenUp,
+    PenDown,
+    SetFillColor(Color),
+    Filled(Color, Box<Token>), // outline traced by the block gets filled
+    Fill,
+    Label(Word),
+    SetLabelHeight(Box<Expr>),
+    SetLabelFont(Word),
     Stop,
+    Comment(String, Span), // text after the `;`, ignored when running
+    Source(Span),          // where the instruction that follows is written
+}
+
+/// Whether the tokens hold no instruction to run, only comments and spans
+pub fn no_instructions(tokens: &[Token]) -> bool {
+    tokens
+        .iter()
+        .all(|token| matches!(token, Token::Comment(..) | Token::Source(_)))
+}
+
+/// Values collected by a rest input
+#[derive(Debug, Clone)]
+pub enum List {
+    Param(String),
+    Values(Vec<f64>),
+}
+
+/// Element of a list by its position counted from 1, wrapping around like
+/// palette numbers do. Empty lists give 0.
+pub fn item(values: &[f64], index: f64) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    let position = (index as i64).saturating_sub(1);
+    values[position.rem_euclid(values.len() as i64) as usize]
 }
 
 #[derive(Debug, Clone)]
 pub enum Expr {
     Arg(Arg),
+    Count(List),           // count :items
+    Item(Box<Expr>, List), // item 2 :items
     Mul(Box<Expr>, Box<Expr>),
     Div(Box<Expr>, Box<Expr>),
     Add(Box<Expr>, Box<Expr>),
@@ -63,40 +179,73 @@ pub enum Expr {
 }
 
 impl Expr {
-    pub fn evaluate(&self) -> f64 {
+    pub fn evaluate(&self, who: usize) -> f64 {
         match self {
             Expr::Arg(Arg::Val(value)) => *value,
+            Expr::Arg(Arg::Who) => who as f64,
             Expr::Arg(Arg::Param(param)) => {
                 panic!(
                     "Parameter '{}' found during evaluation",
                     param
                 );
             }
-            Expr::Mul(lhs, rhs) => lhs.evaluate() * rhs.evaluate(),
-            Expr::Div(lhs, rhs) => lhs.evaluate() / rhs.evaluate(),
-            Expr::Add(lhs, rhs) => lhs.evaluate() + rhs.evaluate(),
-            Expr::Sub(lhs, rhs) => lhs.evaluate() - rhs.evaluate(),
+            Expr::Count(List::Values(values)) => values.len() as f64,
+            Expr::Item(index, List::Values(values)) => item(values, index.evaluate(who)),
+            Expr::Count(List::Param(param)) | Expr::Item(_, List::Param(param)) => {
+                panic!("Parameter '{}' found during evaluation", param);
+            }
+            Expr::Mul(lhs, rhs) => lhs.evaluate(who) * rhs.evaluate(who),
+            Expr::Div(lhs, rhs) => lhs.evaluate(who) / rhs.evaluate(who),
+            Expr::Add(lhs, rhs) => lhs.evaluate(who) + rhs.evaluate(who),
+            Expr::Sub(lhs, rhs) => lhs.evaluate(who) - rhs.evaluate(who),
         }
     }
 }
 
 pub struct Fun {
     pub params: Vec<String>,
+    /// Default values of the optional inputs, which come after the required ones
+    pub defaults: Vec<Expr>,
+    /// Input collecting the values given after all of the others
+    pub rest: Option<String>,
     pub body: Vec<Token>,
+    /// Where `to`, the name and the inputs are written
+    pub span: Span,
+    /// Where the `end` of the definition is written
+    pub end: Span,
 }
 
 impl Fun {
     pub fn new(commands: Vec<Token>, params: Vec<String>) -> Fun {
         Fun {
             params,
+            defaults: vec![],
+            rest: None,
             body: commands,
+            span: Span::default(),
+            end: Span::default(),
         }
     }
+
+    /// Number of inputs taken by a call without parentheses
     pub fn arity(&self) -> usize {
-        self.params.len()
+        self.params.len() - self.defaults.len()
+    }
+
+    /// Whether a parenthesised call may give that many inputs
+    pub fn accepts(&self, count: usize) -> bool {
+        count >= self.arity() && (self.rest.is_some() || count <= self.params.len())
     }
 }
 
+/// Values of the inputs of a procedure call
+#[derive(Debug, Default)]
+pub struct Bindings {
+    pub numbers: HashMap<String, f64>,
+    pub lists: HashMap<String, Vec<f64>>,
+}
+
+#[derive(Default)]
 pub struct Functions {
     pub functions: HashMap<String, Fun>,
 }
@@ -112,42 +261,42 @@ impl Functions {
         self.functions.get(label)
     }
 
-    pub fn push(&mut self, (label, commands, params): (String, Vec<Token>, Vec<String>)) {
-        self.functions.insert(label, Fun::new(commands, params));
+    pub fn push(&mut self, label: String, fun: Fun) {
+        self.functions.insert(label, fun);
     }
 
     pub fn contains(&self, label: &String) -> bool {
         self.functions.contains_key(label)
     }
 
-    pub fn labels(&self) -> Vec<&String> {
-        self.functions.keys().collect()
-    }
-
-    pub fn get_commands(&self, label: &String, args: &Vec<Expr>) -> Vec<Token> {
+    pub fn get_commands(&self, label: &String, args: &[Expr], who: usize) -> Vec<Token> {
         let fun = self.get(label).unwrap();
-        let param_evaluator: HashMap<String, f64> = fun
-                        .params
-                        .iter()
-                        .zip(args.iter())
-                        .map(|(param, expr)| {
-                            (
-                                param.clone(),
-                                expr.evaluate(), // Evaluate the expression argument
-                                // All of the substitutions were done by the parser
-                            )
-                        })
-                        .collect();
-
-        println!("Calling function with parameters: {:?}", param_evaluator);
-
-        let commands = fun
-                        .body
-                        .iter()
-                        .map(|token| substitute_token(token, &param_evaluator))
-                        .collect::<Vec<Token>>();
-
-                    //println!("Commands before wrapping {:?}", commands);
-        wrap_fn_call(commands, self)
+        let mut param_evaluator = Bindings::default();
+        for (i, param) in fun.params.iter().enumerate() {
+            // Inputs are evaluated for the calling turtle, missing ones
+            // taking their default
+            let value = match (args.get(i), i.checked_sub(fun.arity())) {
+                (Some(expr), _) => expr.evaluate(who),
+                // Defaults may refer to the inputs before them
+                (None, Some(optional)) => {
+                    substitute_expr(&fun.defaults[optional], &param_evaluator).evaluate(who)
+                }
+                (None, None) => panic!("Function '{}' expected {} arguments", label, fun.arity()),
+            };
+            param_evaluator.numbers.insert(param.clone(), value);
+        }
+        if let Some(rest) = &fun.rest {
+            let values = args
+                .iter()
+                .skip(fun.params.len())
+                .map(|expr| expr.evaluate(who))
+                .collect();
+            param_evaluator.lists.insert(rest.clone(), values);
+        }
+
+        fun.body
+            .iter()
+            .map(|token| substitute_token(token, &param_evaluator))
+            .collect()
     }
 }
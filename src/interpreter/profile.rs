@@ -0,0 +1,139 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::ops::AddAssign;
+
+use crate::interpreter::image::*;
+use crate::interpreter::lexer::Span;
+
+/// Work done by a procedure or a line of the program
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Counts {
+    pub calls: u64,
+    /// Turtle commands executed
+    pub primitives: u64,
+    /// Length of the lines, arcs and filled outlines drawn
+    pub length: f64,
+    pub segments: u64,
+}
+
+impl AddAssign for Counts {
+    fn add_assign(&mut self, other: Counts) {
+        self.calls += other.calls;
+        self.primitives += other.primitives;
+        self.length += other.length;
+        self.segments += other.segments;
+    }
+}
+
+/// Where a program spends its work. Procedures count what they do along with
+/// the procedures they call, lines only count their own instructions. The
+/// outline of a filled shape is drawn by its `filled` instruction.
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    pub procedures: BTreeMap<String, Counts>,
+    /// By line of the program, counted from 1
+    pub lines: BTreeMap<usize, Counts>,
+    /// Turtle commands executed under each stack of procedure calls,
+    /// outermost first
+    pub stacks: BTreeMap<Vec<String>, u64>,
+    source: String,
+    newlines: Vec<usize>,
+}
+
+/// Name given to the top level commands in stacks
+const TOP_LEVEL: &str = "(top level)";
+
+impl Profile {
+    pub fn new(source: &str) -> Self {
+        Self {
+            source: source.to_string(),
+            newlines: source.match_indices('\n').map(|(i, _)| i).collect(),
+            ..Self::default()
+        }
+    }
+
+    fn line(&self, span: Span) -> usize {
+        self.newlines
+            .partition_point(|&newline| newline < span.start)
+            + 1
+    }
+
+    /// Counts a call to a procedure, made from the origin
+    pub fn call(&mut self, procedure: &str, origin: &Origin) {
+        let call = Counts {
+            calls: 1,
+            ..Counts::default()
+        };
+        *self.procedures.entry(procedure.to_string()).or_default() += call;
+        *self.lines.entry(self.line(origin.span)).or_default() += call;
+    }
+
+    /// Counts the turtle commands executed and the drawing made at the origin
+    pub fn record(&mut self, origin: &Origin, primitives: u64, (length, segments): (f64, usize)) {
+        let counts = Counts {
+            calls: 0,
+            primitives,
+            length,
+            segments: segments as u64,
+        };
+        // Recursive procedures count once
+        let procedures: BTreeSet<&str> =
+            origin.calls.iter().map(|(name, _)| name.as_str()).collect();
+        for procedure in procedures {
+            *self.procedures.entry(procedure.to_string()).or_default() += counts;
+        }
+        *self.lines.entry(self.line(origin.span)).or_default() += counts;
+        if primitives > 0 {
+            let stack = origin.calls.iter().map(|(name, _)| name.clone()).collect();
+            *self.stacks.entry(stack).or_default() += primitives;
+        }
+    }
+
+    /// Counts by procedure then by line, as a table for reading
+    pub fn table(&self) -> String {
+        let mut table = String::new();
+        let header = |table: &mut String, title: &str| {
+            let _ = writeln!(
+                table,
+                "{:<20}{:>10}{:>12}{:>12}{:>10}",
+                title, "calls", "primitives", "length", "segments"
+            );
+        };
+        let row = |table: &mut String, name: &str, counts: &Counts, text: &str| {
+            let _ = writeln!(
+                table,
+                "{:<20}{:>10}{:>12}{:>12.2}{:>10}  {}",
+                name, counts.calls, counts.primitives, counts.length, counts.segments, text
+            );
+        };
+
+        header(&mut table, "procedure");
+        for (procedure, counts) in &self.procedures {
+            row(&mut table, procedure, counts, "");
+        }
+        table.push('\n');
+        header(&mut table, "line");
+        let lines: Vec<&str> = self.source.lines().collect();
+        for (&line, counts) in &self.lines {
+            let text = lines.get(line - 1).map_or("", |text| text.trim());
+            row(&mut table, &line.to_string(), counts, text);
+        }
+        table
+            .lines()
+            .map(|line| line.trim_end().to_string() + "\n")
+            .collect()
+    }
+
+    /// Stacks in the folded format read by flamegraph tools, weighted by the
+    /// turtle commands executed
+    pub fn folded(&self) -> String {
+        let mut folded = String::new();
+        for (stack, primitives) in &self.stacks {
+            let frames: Vec<&str> = std::iter::once(TOP_LEVEL)
+                .chain(stack.iter().map(String::as_str))
+                .collect();
+            let _ = writeln!(folded, "{} {}", frames.join(";"), primitives);
+        }
+        folded
+    }
+}
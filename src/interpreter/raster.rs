@@ -0,0 +1,268 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufWriter};
+
+use crate::interpreter::font::*;
+
+/// Converts an SVG color name or `#rrggbb` value into RGB, unknown names are black
+pub fn rgb(color: &str) -> [u8; 3] {
+    if let Some(hex) = color.strip_prefix('#') {
+        if hex.len() == 6 {
+            if let Ok(value) = u32::from_str_radix(hex, 16) {
+                return [(value >> 16) as u8, (value >> 8) as u8, value as u8];
+            }
+        }
+    }
+    match color.to_lowercase().as_str() {
+        "blue" => [0, 0, 255],
+        "lime" => [0, 255, 0],
+        "cyan" | "aqua" => [0, 255, 255],
+        "red" => [255, 0, 0],
+        "magenta" | "fuchsia" => [255, 0, 255],
+        "yellow" => [255, 255, 0],
+        "white" => [255, 255, 255],
+        "brown" => [165, 42, 42],
+        "tan" => [210, 180, 140],
+        "green" => [0, 128, 0],
+        "darkgreen" => [0, 100, 0],
+        "aquamarine" => [127, 255, 212],
+        "salmon" => [250, 128, 114],
+        "purple" => [128, 0, 128],
+        "orange" => [255, 165, 0],
+        "gray" | "grey" => [128, 128, 128],
+        "pink" => [255, 192, 203],
+        "navy" => [0, 0, 128],
+        _ => [0, 0, 0],
+    }
+}
+
+/// Pixel canvas centered on the origin of the turtle's coordinates
+pub struct Raster {
+    width: usize,
+    height: usize,
+    pixels: Vec<[u8; 3]>,
+}
+
+impl Raster {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![[255, 255, 255]; width * height],
+        }
+    }
+
+    fn pixel(&self, x: f64, y: f64) -> Option<usize> {
+        let px = (x + self.width as f64 / 2.0).floor();
+        let py = (y + self.height as f64 / 2.0).floor();
+        if px < 0.0 || py < 0.0 || px >= self.width as f64 || py >= self.height as f64 {
+            return None;
+        }
+        Some(py as usize * self.width + px as usize)
+    }
+
+    fn plot(&mut self, x: f64, y: f64, color: [u8; 3]) {
+        if let Some(index) = self.pixel(x, y) {
+            self.pixels[index] = color;
+        }
+    }
+
+    /// Draws a segment with a square brush as wide as the pen. Only the part
+    /// of the segment where the brush reaches the canvas is stepped through.
+    pub fn line(&mut self, from: (f64, f64), to: (f64, f64), width: f64, color: [u8; 3]) {
+        // A brush wider than the canvas covers all of it from anywhere near
+        let radius = ((width - 1.0) / 2.0).clamp(0.0, (self.width + self.height) as f64);
+        let Some(((x1, y1), (x2, y2))) = self.clip(from, to, radius + 1.0) else {
+            return;
+        };
+        let steps = (x2 - x1).abs().max((y2 - y1).abs()).ceil().max(1.0) as usize;
+        for step in 0..=steps {
+            let t = step as f64 / steps as f64;
+            // Once the whole canvas is painted the rest of the line adds nothing
+            if self.brush(x1 + (x2 - x1) * t, y1 + (y2 - y1) * t, radius, color) {
+                return;
+            }
+        }
+    }
+
+    /// Part of a segment lying within `margin` of the canvas, `None` when the
+    /// segment stays further away
+    fn clip(
+        &self,
+        (x1, y1): (f64, f64),
+        (x2, y2): (f64, f64),
+        margin: f64,
+    ) -> Option<((f64, f64), (f64, f64))> {
+        let half_width = self.width as f64 / 2.0 + margin;
+        let half_height = self.height as f64 / 2.0 + margin;
+        let (dx, dy) = (x2 - x1, y2 - y1);
+        // Range of the segment inside each edge in turn, with the offset
+        // towards the edge and the distance to it
+        let (mut enter, mut leave) = (0.0_f64, 1.0_f64);
+        for (offset, distance) in [
+            (-dx, x1 + half_width),
+            (dx, half_width - x1),
+            (-dy, y1 + half_height),
+            (dy, half_height - y1),
+        ] {
+            if offset == 0.0 {
+                if distance < 0.0 {
+                    return None;
+                }
+            } else if offset < 0.0 {
+                enter = enter.max(distance / offset);
+            } else {
+                leave = leave.min(distance / offset);
+            }
+        }
+        if enter > leave {
+            return None;
+        }
+        // Ends inside the canvas are kept exactly as given
+        let point = |t: f64| match t {
+            t if t <= 0.0 => (x1, y1),
+            t if t >= 1.0 => (x2, y2),
+            t => (x1 + dx * t, y1 + dy * t),
+        };
+        Some((point(enter), point(leave)))
+    }
+
+    /// Paints the square brush centered on a point, row by row and only over
+    /// the canvas, telling whether it covered all of it
+    fn brush(&mut self, x: f64, y: f64, radius: f64, color: [u8; 3]) -> bool {
+        let size = (2.0 * radius).floor() as i64 + 1;
+        let left = (x - radius + self.width as f64 / 2.0).floor() as i64;
+        let top = (y - radius + self.height as f64 / 2.0).floor() as i64;
+        let columns = left.max(0) as usize..(left + size).clamp(0, self.width as i64) as usize;
+        let rows = top.max(0) as usize..(top + size).clamp(0, self.height as i64) as usize;
+        if columns.is_empty() {
+            return false;
+        }
+        for row in rows.clone() {
+            let start = row * self.width;
+            self.pixels[start + columns.start..start + columns.end].fill(color);
+        }
+        columns.len() == self.width && rows.len() == self.height
+    }
+
+    pub fn polyline(&mut self, points: &[(f64, f64)], width: f64, color: [u8; 3]) {
+        for pair in points.windows(2) {
+            self.line(pair[0], pair[1], width, color);
+        }
+    }
+
+    /// Fills the inside of a closed polygon using the even-odd rule
+    pub fn polygon(&mut self, points: &[(f64, f64)], color: [u8; 3]) {
+        let half_height = self.height as f64 / 2.0;
+        let (top, bottom) = points
+            .iter()
+            .fold((f64::MAX, f64::MIN), |(top, bottom), point| {
+                (top.min(point.1), bottom.max(point.1))
+            });
+        let first_row = (top + half_height).floor().max(0.0) as usize;
+        let last_row = ((bottom + half_height).ceil().max(0.0) as usize).min(self.height);
+        for row in first_row..last_row {
+            let y = row as f64 - half_height + 0.5;
+            let mut crossings: Vec<f64> = Vec::new();
+            for (i, &(x1, y1)) in points.iter().enumerate() {
+                let (x2, y2) = points[(i + 1) % points.len()];
+                if (y1 <= y) != (y2 <= y) {
+                    crossings.push(x1 + (y - y1) / (y2 - y1) * (x2 - x1));
+                }
+            }
+            crossings.sort_by(|a, b| a.total_cmp(b));
+            for pair in crossings.chunks_exact(2) {
+                // Pixel centers between the two crossings
+                let mut x = (pair[0] - 0.5).ceil() + 0.5;
+                while x < pair[1] {
+                    self.plot(x, y, color);
+                    x += 1.0;
+                }
+            }
+        }
+    }
+
+    /// Draws text with the built-in bitmap font, its baseline starting at the
+    /// position and rotated clockwise by the angle in degrees
+    pub fn text(
+        &mut self,
+        position: (f64, f64),
+        text: &str,
+        height: f64,
+        angle: f64,
+        color: [u8; 3],
+    ) {
+        let scale = height / GLYPH_HEIGHT as f64;
+        let (sin, cos) = angle.to_radians().sin_cos();
+        let place = |x: f64, y: f64| {
+            (
+                position.0 + x * cos - y * sin,
+                position.1 + x * sin + y * cos,
+            )
+        };
+
+        for (i, character) in text.chars().enumerate() {
+            for (column, row) in glyph(character) {
+                let x = (i * GLYPH_WIDTH + column) as f64 * scale;
+                let y = (row as f64 - 7.0) * scale;
+                let cell = [
+                    place(x, y),
+                    place(x + scale, y),
+                    place(x + scale, y + scale),
+                    place(x, y + scale),
+                ];
+                self.polygon(&cell, color);
+            }
+        }
+    }
+
+    /// Paint bucket fill of the area around the point sharing its color
+    pub fn flood_fill(&mut self, x: f64, y: f64, color: [u8; 3]) {
+        let Some(start) = self.pixel(x, y) else {
+            return;
+        };
+        let target = self.pixels[start];
+        if target == color {
+            return;
+        }
+
+        let mut queue = VecDeque::from([start]);
+        self.pixels[start] = color;
+        while let Some(index) = queue.pop_front() {
+            let (px, py) = (index % self.width, index / self.width);
+            let mut neighbours = vec![];
+            if px > 0 {
+                neighbours.push(index - 1);
+            }
+            if px + 1 < self.width {
+                neighbours.push(index + 1);
+            }
+            if py > 0 {
+                neighbours.push(index - self.width);
+            }
+            if py + 1 < self.height {
+                neighbours.push(index + self.width);
+            }
+            for neighbour in neighbours {
+                if self.pixels[neighbour] == target {
+                    self.pixels[neighbour] = color;
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+    }
+
+    pub fn save(&self, file_path: &str) -> io::Result<()> {
+        let file = File::create(file_path)?;
+        let mut encoder =
+            png::Encoder::new(BufWriter::new(file), self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let data: Vec<u8> = self.pixels.iter().flatten().copied().collect();
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&data))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,129 @@
+use crate::interpreter::turtle::TurtleState;
+
+// Coordinates follow the turtle's conventions: the origin at the center of
+// the drawing and the y axis pointing up.
+
+/// Pen a shape was drawn with
+#[derive(Debug, Clone, PartialEq)]
+pub struct Style {
+    pub color: String,
+    pub width: f64,
+}
+
+/// Straight line drawn by the turtle
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub from: (f64, f64),
+    pub to: (f64, f64),
+    pub style: Style,
+}
+
+impl Segment {
+    pub fn length(&self) -> f64 {
+        (self.to.0 - self.from.0).hypot(self.to.1 - self.from.1)
+    }
+}
+
+/// Arc, circle or ellipse drawn around the turtle, angles in degrees
+/// measured clockwise from the x axis
+#[derive(Debug, Clone, PartialEq)]
+pub struct Curve {
+    pub center: (f64, f64),
+    pub radii: (f64, f64),
+    /// Rotation of the ellipse axes
+    pub rotation: f64,
+    /// Angle of the starting point, relative to the rotated axes
+    pub start: f64,
+    /// Angle covered, negative values go anticlockwise
+    pub sweep: f64,
+    pub style: Style,
+}
+
+impl Curve {
+    pub fn point(&self, angle: f64) -> (f64, f64) {
+        let (sin, cos) = angle.to_radians().sin_cos();
+        let (rot_sin, rot_cos) = self.rotation.to_radians().sin_cos();
+        let (x, y) = (self.radii.0 * cos, self.radii.1 * sin);
+        (
+            self.center.0 + x * rot_cos - y * rot_sin,
+            self.center.1 - x * rot_sin - y * rot_cos,
+        )
+    }
+
+    /// Approximates the curve with a point every degree
+    pub fn points(&self) -> Vec<(f64, f64)> {
+        let steps = (self.sweep.abs().ceil() as usize).max(1);
+        (0..=steps)
+            .map(|i| self.point(self.start + self.sweep * i as f64 / steps as f64))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fill {
+    /// Outline drawn in a `filled` block, closed from its last point back to
+    /// its first
+    Shape {
+        points: Vec<(f64, f64)>,
+        color: String,
+        outline: Style,
+    },
+    /// Paint poured by `fill` at the turtle's position
+    Flood { point: (f64, f64), color: String },
+}
+
+/// Text written by `label`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Text {
+    pub position: (f64, f64),
+    /// Clockwise rotation of the baseline in degrees
+    pub rotation: f64,
+    pub text: String,
+    pub height: f64,
+    pub font: String,
+    pub style: Style,
+}
+
+/// Everything a program drew, as data to make assertions on, in the order
+/// it was drawn
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Recording {
+    /// Lines drawn with the pen down, outside of `filled` blocks
+    pub segments: Vec<Segment>,
+    pub curves: Vec<Curve>,
+    pub fills: Vec<Fill>,
+    pub labels: Vec<Text>,
+    /// Final state of every turtle, by number
+    pub turtles: Vec<TurtleState>,
+}
+
+impl Recording {
+    /// Points of the lines drawn one after the other in the same style, each
+    /// starting within the tolerance of where the previous one ended, then of
+    /// the filled outlines closed back to their first point
+    pub fn paths(&self, tolerance: f64) -> Vec<Vec<(f64, f64)>> {
+        let near = |a: (f64, f64), b: (f64, f64)| (a.0 - b.0).hypot(a.1 - b.1) <= tolerance;
+        let mut paths: Vec<Vec<(f64, f64)>> = vec![];
+        let mut style = None;
+        for segment in &self.segments {
+            match paths.last_mut() {
+                Some(path)
+                    if style == Some(&segment.style)
+                        && near(path[path.len() - 1], segment.from) =>
+                {
+                    path.push(segment.to)
+                }
+                _ => paths.push(vec![segment.from, segment.to]),
+            }
+            style = Some(&segment.style);
+        }
+        for fill in &self.fills {
+            if let Fill::Shape { points, .. } = fill {
+                let mut path = points.clone();
+                path.extend(points.first());
+                paths.push(path);
+            }
+        }
+        paths
+    }
+}
@@ -0,0 +1,92 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use serde_json::{json, Value};
+
+use crate::interpreter::image::*;
+use crate::interpreter::turtle::*;
+use crate::interpreter::*;
+
+/// Record of the turtle commands executed, written as JSON Lines: one object
+/// per command and turtle it applies to, with the depth of procedure calls it
+/// ran at, its inputs and the state of the turtle before and after
+pub struct Trace {
+    output: BufWriter<File>,
+}
+
+impl Trace {
+    pub fn create(path: &str) -> Result<Self, LogoError> {
+        let file = File::create(path).map_err(|error| LogoError::Trace(error.to_string()))?;
+        Ok(Self {
+            output: BufWriter::new(file),
+        })
+    }
+
+    /// Applies a command to a turtle, writing its line even when it fails
+    pub fn apply(
+        &mut self,
+        turtle: &mut Turtle,
+        depth: usize,
+        command: &Command,
+        image: &mut Image,
+    ) -> Result<(), LogoError> {
+        let before = turtle.state();
+        let result = turtle.apply(command, image);
+        let after = turtle.state();
+
+        let (primitive, inputs) = primitive(command);
+        let mut line = json!({
+            "depth": depth,
+            "turtle": after.id,
+            "primitive": primitive,
+            "inputs": inputs,
+            "before": state(&before),
+            "after": state(&after),
+        });
+        if let Err(error) = &result {
+            line["error"] = json!(error.to_string());
+        }
+        let written = writeln!(self.output, "{}", line);
+        result?;
+        written.map_err(|error| LogoError::Trace(error.to_string()))
+    }
+}
+
+/// Name of the primitive a command comes from, with its inputs
+fn primitive(command: &Command) -> (&'static str, Vec<Value>) {
+    match command {
+        Command::Forward(distance) => ("forward", vec![json!(distance)]),
+        Command::Back(distance) => ("back", vec![json!(distance)]),
+        Command::TurnRight(angle) => ("right", vec![json!(angle)]),
+        Command::TurnLeft(angle) => ("left", vec![json!(angle)]),
+        Command::Arc(angle, radius) => ("arc", vec![json!(angle), json!(radius)]),
+        Command::Circle(radius) => ("circle", vec![json!(radius)]),
+        Command::Ellipse(width, height) => ("ellipse", vec![json!(width), json!(height)]),
+        Command::SetFillColor(color) => ("setfillcolor", vec![json!(color)]),
+        Command::Fill => ("fill", vec![]),
+        Command::Label(text) => ("label", vec![json!(text)]),
+        Command::SetLabelHeight(height) => ("setlabelheight", vec![json!(height)]),
+        Command::SetLabelFont(font) => ("setlabelfont", vec![json!(font)]),
+        Command::SetPenColor(color) => ("setpencolor", vec![json!(color)]),
+        Command::SetPenSize(size) => ("setpensize", vec![json!(size)]),
+        Command::Boundary(Boundary::Wrap) => ("wrap", vec![]),
+        Command::Boundary(Boundary::Window) => ("window", vec![]),
+        Command::Boundary(Boundary::Fence) => ("fence", vec![]),
+        Command::PenUp => ("penup", vec![]),
+        Command::PenDown => ("pendown", vec![]),
+        Command::ShowTurtle => ("showturtle", vec![]),
+        Command::HideTurtle => ("hideturtle", vec![]),
+    }
+}
+
+fn state(state: &TurtleState) -> Value {
+    json!({
+        "x": state.x,
+        "y": state.y,
+        "heading": state.heading,
+        "pen_down": state.pen_down,
+        "pen_color": state.pen_color,
+        "pen_size": state.pen_size,
+        "visible": state.visible,
+    })
+}
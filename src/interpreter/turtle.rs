@@ -1,108 +1,352 @@
+use std::rc::Rc;
+
 use crate::interpreter::image::*;
 use crate::interpreter::*;
 
+/// What happens when the turtle reaches the edge of the drawing area
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Boundary {
+    /// Reappears on the opposite edge
+    Wrap,
+    /// Keeps going beyond the visible area
+    Window,
+    /// Refuses to move past the edge
+    Fence,
+}
+
+/// A drawing or turtle state command with its inputs evaluated
+#[derive(Debug, Clone)]
+pub enum Command {
+    Forward(f64),
+    Back(f64),
+    TurnRight(f64),
+    TurnLeft(f64),
+    Arc(f64, f64), // angle, radius
+    Circle(f64),
+    Ellipse(f64, f64),
+    SetFillColor(String),
+    Fill,
+    Label(String),
+    SetLabelHeight(f64),
+    SetLabelFont(String),
+    SetPenColor(String),
+    SetPenSize(f64),
+    Boundary(Boundary),
+    PenUp,
+    PenDown,
+    ShowTurtle,
+    HideTurtle,
+}
+
+impl Command {
+    /// Evaluates the inputs of a primitive token for the given turtle
+    pub fn evaluate(token: &Token, who: usize) -> Command {
+        match token {
+            Token::Forward(expr) => Command::Forward(expr.evaluate(who)),
+            Token::Back(expr) => Command::Back(expr.evaluate(who)),
+            Token::TurnRight(expr) => Command::TurnRight(expr.evaluate(who)),
+            Token::TurnLeft(expr) => Command::TurnLeft(expr.evaluate(who)),
+            Token::Arc(angle, radius) => Command::Arc(angle.evaluate(who), radius.evaluate(who)),
+            Token::Circle(radius) => Command::Circle(radius.evaluate(who)),
+            Token::Ellipse(width, height) => {
+                Command::Ellipse(width.evaluate(who), height.evaluate(who))
+            }
+            Token::SetFillColor(color) => Command::SetFillColor(color.evaluate(who)),
+            Token::Fill => Command::Fill,
+            Token::Label(word) => Command::Label(word.evaluate(who)),
+            Token::SetLabelHeight(expr) => Command::SetLabelHeight(expr.evaluate(who)),
+            Token::SetLabelFont(word) => Command::SetLabelFont(word.evaluate(who)),
+            Token::SetPenColor(color) => Command::SetPenColor(color.evaluate(who)),
+            Token::SetPenSize(expr) => Command::SetPenSize(expr.evaluate(who)),
+            Token::Wrap => Command::Boundary(Boundary::Wrap),
+            Token::Window => Command::Boundary(Boundary::Window),
+            Token::Fence => Command::Boundary(Boundary::Fence),
+            Token::PenUp => Command::PenUp,
+            Token::PenDown => Command::PenDown,
+            Token::ShowTurtle => Command::ShowTurtle,
+            Token::HideTurtle => Command::HideTurtle,
+            _ => panic!("Unsupported token in execute: {:?}", token),
+        }
+    }
+}
+
+/// Where a turtle is and how it draws, in Logo's conventions: the y axis
+/// points up and headings are measured clockwise from north
+#[derive(Debug, Clone, PartialEq)]
+pub struct TurtleState {
+    pub id: usize,
+    pub x: f64,
+    pub y: f64,
+    pub heading: f64,
+    pub pen_down: bool,
+    pub pen_color: String,
+    pub pen_size: f64,
+    pub visible: bool,
+}
+
 #[derive(Debug)]
 pub struct Turtle {
+    id: usize,
     x: f64,
     y: f64,
     angle: f64,
+    boundary: Boundary,
+    visible: bool,
+    pen_down: bool,
+    stroke: Stroke,
+    fill_color: String,
+    label_height: f64,
+    label_font: String,
+    /// Points visited inside a `filled` block
+    outline: Option<Vec<(f64, f64)>>,
 }
 
 impl Turtle {
-    pub fn new() -> Self {
+    pub fn new(id: usize, boundary: Boundary) -> Self {
         Self {
+            id,
             x: 0.0,
             y: 0.0,
             angle: 270.0,
+            boundary,
+            visible: true,
+            pen_down: true,
+            stroke: Stroke::new(),
+            fill_color: String::from("black"),
+            label_height: 12.0,
+            label_font: String::from("sans-serif"),
+            outline: None,
         }
     }
 
-    pub fn execute(&mut self, token: &Token, image: &mut Image, fns: &Functions) -> bool {
-        match token {
-            Token::Forward(expr) => {
-                let distance = expr.evaluate();
-                let radians = self.angle.to_radians();
-                let new_x = self.x + distance * radians.cos();
-                let new_y = self.y + distance * radians.sin();
-
-                image.add_line(self.x, self.y, new_x, new_y);
-                self.x = new_x;
-                self.y = new_y;
-            }
-            Token::Back(expr) => {
-                let distance = expr.evaluate();
-                let radians = self.angle.to_radians();
-                let new_x = self.x - distance * radians.cos();
-                let new_y = self.y - distance * radians.sin();
-
-                image.add_line(self.x, self.y, new_x, new_y);
-                self.x = new_x;
-                self.y = new_y;
-            }
-            Token::TurnRight(expr) => {
-                let angle = expr.evaluate();
-                self.angle = (self.angle + angle) % 360.0;
-            }
-            Token::TurnLeft(expr) => {
-                let angle = expr.evaluate();
-                self.angle = (self.angle - angle) % 360.0;
-            }
-            Token::Repeat(expr, body) => {
-                let times = expr.evaluate() as u32;
-                for _ in 0..times {
-                    match body.as_ref() {
-                        Token::Bracket(tokens) => {
-                            for token in tokens {
-                                if !self.execute(token, image, fns) {
-                                    return false;
-                                };
-                            }
-                        }
-                        _ => panic!("Repeat body must be a Bracket token"),
-                    }
+    pub fn state(&self) -> TurtleState {
+        TurtleState {
+            id: self.id,
+            x: self.x,
+            // The image has its y axis pointing down, subtracting keeps a
+            // turtle on the axis from being at -0
+            y: 0.0 - self.y,
+            // Angles on the image start east
+            heading: (self.angle + 90.0).rem_euclid(360.0),
+            pen_down: self.pen_down,
+            pen_color: self.stroke.color.to_string(),
+            pen_size: self.stroke.width,
+            visible: self.visible,
+        }
+    }
+
+    fn move_to(&mut self, new_x: f64, new_y: f64, image: &mut Image) {
+        match self.outline.as_mut() {
+            Some(outline) => outline.push((new_x, new_y)),
+            None if self.pen_down => image.add_line((self.x, self.y), (new_x, new_y), &self.stroke),
+            None => {}
+        }
+        self.x = new_x;
+        self.y = new_y;
+    }
+
+    /// Turns by the given angle, wrapping the heading once past a full turn
+    fn turn(&mut self, angle: f64) {
+        let angle = self.angle + angle;
+        self.angle = if angle.abs() < 360.0 {
+            angle
+        } else {
+            angle % 360.0
+        };
+    }
+
+    /// Moves along the heading, applying the boundary mode at the edges
+    fn walk(&mut self, distance: f64, image: &mut Image) -> Result<(), LogoError> {
+        finite(distance)?;
+        let (sin, cos) = self.angle.to_radians().sin_cos();
+        let (new_x, new_y) = (self.x + distance * cos, self.y + distance * sin);
+        let (half_width, half_height) = (WIDTH / 2.0, HEIGHT / 2.0);
+
+        match self.boundary {
+            Boundary::Window => self.move_to(new_x, new_y, image),
+            Boundary::Fence => {
+                if new_x.abs() > half_width || new_y.abs() > half_height {
+                    return Err(LogoError::OutOfBounds);
                 }
+                self.move_to(new_x, new_y, image);
             }
-            Token::FnCall(label, args) => {
-                //println!("Begin function call");
-                if fns.contains(label) {
-                    for command in fns.get_commands(label, args) {
-                        if !self.execute(&command, image, fns) {
-                            return true; // exit the scope
-                        };
-                    }
-                } else {
-                    panic!("Function '{}' not found in the environment", label);
-                }
+            // Most moves stay inside the drawing area
+            Boundary::Wrap if new_x.abs() < half_width && new_y.abs() < half_height => {
+                self.move_to(new_x, new_y, image)
             }
-            Token::Bracket(tokens) => {
-                for token in tokens {
-                    if !self.execute(token, image, fns) {
-                        return false;
+            Boundary::Wrap => {
+                let (dx, dy) = (distance.signum() * cos, distance.signum() * sin);
+                let mut remaining = distance.abs();
+                let mut pieces = 0;
+                loop {
+                    // Distance to the edges the turtle is heading towards
+                    let to_x = match dx {
+                        dx if dx > 0.0 => (half_width - self.x) / dx,
+                        dx if dx < 0.0 => (-half_width - self.x) / dx,
+                        _ => f64::INFINITY,
                     };
-                }
-            }
-            Token::If(log_expr, body) => {
-                //println!("Evaluating logical expression {:?}", log_expr);
-                if let LogExpr::Val(true) = log_expr.evaluate() {
-                    //println!("Evaluated true");
-                    match body.as_ref() {
-                        Token::Bracket(tokens) => {
-                            for token in tokens {
-                                if !self.execute(token, image, fns) {
-                                    return false;
-                                };
-                            }
-                        }
-                        _ => panic!("If body must be a Bracket token"),
+                    let to_y = match dy {
+                        dy if dy > 0.0 => (half_height - self.y) / dy,
+                        dy if dy < 0.0 => (-half_height - self.y) / dy,
+                        _ => f64::INFINITY,
+                    };
+                    let to_edge = to_x.min(to_y).max(0.0);
+
+                    if remaining <= to_edge {
+                        self.move_to(self.x + remaining * dx, self.y + remaining * dy, image);
+                        break;
+                    }
+
+                    self.move_to(self.x + to_edge * dx, self.y + to_edge * dy, image);
+                    remaining -= to_edge;
+                    pieces += 1;
+                    image.wrapped(pieces)?;
+                    if to_x <= to_edge {
+                        self.x = -self.x;
+                    }
+                    if to_y <= to_edge {
+                        self.y = -self.y;
+                    }
+                    if let Some(outline) = self.outline.as_mut() {
+                        outline.push((self.x, self.y));
                     }
                 }
             }
-            Token::Stop => {
-                return false;
+        }
+        Ok(())
+    }
+
+    /// Marks the turtle's final position and heading on the image
+    pub fn show(&self, image: &mut Image) {
+        if self.visible {
+            image.add_turtle(self.x, self.y, self.angle);
+        }
+    }
+
+    /// Starts recording the points visited for a `filled` block
+    pub fn begin_outline(&mut self) -> Option<Vec<(f64, f64)>> {
+        self.outline.replace(vec![(self.x, self.y)])
+    }
+
+    /// Fills the recorded outline and restores the one of an enclosing block
+    pub fn end_outline(&mut self, outer: Option<Vec<(f64, f64)>>, fill: String, image: &mut Image) {
+        let outline = std::mem::replace(&mut self.outline, outer).unwrap_or_default();
+        image.add_polygon(outline, fill, &self.stroke);
+    }
+
+    /// Applies a command whose inputs are already evaluated
+    pub fn apply(&mut self, command: &Command, image: &mut Image) -> Result<(), LogoError> {
+        match command {
+            Command::Forward(distance) => {
+                self.walk(*distance, image)?;
+            }
+            Command::Back(distance) => {
+                self.walk(-distance, image)?;
+            }
+            Command::TurnRight(angle) => {
+                self.turn(finite(*angle)?);
+            }
+            Command::TurnLeft(angle) => {
+                self.turn(-finite(*angle)?);
+            }
+            // Curves are centered on the turtle, which stays in place
+            Command::Arc(angle, radius) => {
+                let (angle, radius) = (finite(*angle)?, finite(*radius)?);
+                image.add_arc(
+                    Arc {
+                        center: (self.x, self.y),
+                        radii: (radius, radius),
+                        rotation: 0.0,
+                        start: self.angle,
+                        // Going round more than once retraces the circle
+                        sweep: angle.clamp(-360.0, 360.0),
+                    },
+                    &self.stroke,
+                );
+            }
+            Command::Circle(radius) => {
+                let radius = finite(*radius)?;
+                image.add_arc(
+                    Arc {
+                        center: (self.x, self.y),
+                        radii: (radius, radius),
+                        rotation: 0.0,
+                        start: self.angle,
+                        sweep: 360.0,
+                    },
+                    &self.stroke,
+                );
+            }
+            Command::Ellipse(width, height) => {
+                // The second radius lies along the heading
+                image.add_arc(
+                    Arc {
+                        center: (self.x, self.y),
+                        radii: (finite(*width)?, finite(*height)?),
+                        rotation: self.angle + 90.0,
+                        start: 0.0,
+                        sweep: 360.0,
+                    },
+                    &self.stroke,
+                );
+            }
+            Command::SetFillColor(color) => {
+                self.fill_color = color.clone();
+            }
+            Command::Fill => {
+                image.add_fill(self.x, self.y, self.fill_color.clone());
+            }
+            Command::Label(text) => {
+                // Upright text when the turtle faces up
+                image.add_label(
+                    Label {
+                        position: (self.x, self.y),
+                        rotation: (self.angle + 90.0).rem_euclid(360.0),
+                        text: text.clone(),
+                        height: self.label_height,
+                        font: self.label_font.clone(),
+                    },
+                    &self.stroke,
+                );
+            }
+            Command::SetLabelHeight(height) => {
+                self.label_height = finite(*height)?;
+            }
+            Command::SetLabelFont(font) => {
+                self.label_font = font.clone();
+            }
+            Command::SetPenColor(color) => {
+                self.stroke.color = Rc::from(color.as_str());
+            }
+            Command::SetPenSize(width) => {
+                self.stroke.width = finite(*width)?;
+            }
+            Command::Boundary(boundary) => {
+                self.boundary = *boundary;
+            }
+            Command::PenUp => {
+                self.pen_down = false;
+            }
+            Command::PenDown => {
+                self.pen_down = true;
+            }
+            Command::ShowTurtle => {
+                self.visible = true;
+            }
+            Command::HideTurtle => {
+                self.visible = false;
             }
-            _ => panic!("Unsupported token in execute: {:?}", token),
         }
 
-        true
+        Ok(())
+    }
+}
+
+/// Rejects an input that is infinite or not a number, as when dividing by zero
+fn finite(value: f64) -> Result<f64, LogoError> {
+    if value.is_finite() {
+        Ok(value)
+    } else {
+        Err(LogoError::NotFinite)
     }
 }
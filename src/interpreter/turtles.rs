@@ -0,0 +1,175 @@
+use std::collections::BTreeMap;
+
+use crate::interpreter::image::*;
+use crate::interpreter::trace::*;
+use crate::interpreter::turtle::*;
+use crate::interpreter::*;
+
+/// Outlines of the enclosing `filled` block, per turtle
+pub type Outlines = Vec<(usize, Option<Vec<(f64, f64)>>)>;
+
+/// All turtles sharing the image, commands go to the active ones
+pub struct Turtles {
+    turtles: BTreeMap<usize, Turtle>,
+    active: Vec<usize>,
+    boundary: Boundary,
+    trace: Option<Trace>,
+}
+
+/// Applies a command to a turtle, recording it when tracing
+fn apply(
+    turtle: &mut Turtle,
+    trace: &mut Option<Trace>,
+    depth: usize,
+    command: &Command,
+    image: &mut Image,
+) -> Result<(), LogoError> {
+    match trace {
+        Some(trace) => trace.apply(turtle, depth, command, image),
+        None => turtle.apply(command, image),
+    }
+}
+
+impl Turtles {
+    pub fn new() -> Self {
+        Self {
+            turtles: BTreeMap::from([(0, Turtle::new(0, Boundary::Wrap))]),
+            active: vec![0],
+            boundary: Boundary::Wrap,
+            trace: None,
+        }
+    }
+
+    /// Records the commands applied from now on, along with the depth of
+    /// procedure calls they are given
+    pub fn set_trace(&mut self, trace: Trace) {
+        self.trace = Some(trace);
+    }
+
+    /// Number of the first active turtle, used for expressions outside of
+    /// turtle commands
+    pub fn who(&self) -> usize {
+        self.active.first().copied().unwrap_or(0)
+    }
+
+    pub fn active(&self) -> &[usize] {
+        &self.active
+    }
+
+    /// Makes the turtles active, returning the ones that were
+    pub fn set_active(&mut self, ids: Vec<usize>) -> Vec<usize> {
+        std::mem::replace(&mut self.active, ids)
+    }
+
+    pub fn get(&self, id: usize) -> &Turtle {
+        &self.turtles[&id]
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> &mut Turtle {
+        self.turtles.get_mut(&id).unwrap()
+    }
+
+    /// Resolves turtle numbers, creating the turtles that do not exist yet
+    pub fn select(&mut self, ids: &[f64]) -> Vec<usize> {
+        let ids: Vec<usize> = ids.iter().map(|id| id.max(0.0) as usize).collect();
+        for &id in &ids {
+            let boundary = self.boundary;
+            self.turtles
+                .entry(id)
+                .or_insert_with(|| Turtle::new(id, boundary));
+        }
+        ids
+    }
+
+    /// Hatches a turtle numbered after the existing ones, which becomes the only active one
+    pub fn new_turtle(&mut self) {
+        let last = self.turtles.keys().last();
+        let id = match last.map_or(Some(0), |id| id.checked_add(1)) {
+            Some(id) => id,
+            // Past the largest number, the first free one is taken
+            None => (0..)
+                .find(|id| !self.turtles.contains_key(id))
+                .unwrap_or_default(),
+        };
+        self.turtles.insert(id, Turtle::new(id, self.boundary));
+        self.active = vec![id];
+    }
+
+    /// State of every turtle, by number
+    pub fn states(&self) -> Vec<TurtleState> {
+        self.turtles.values().map(Turtle::state).collect()
+    }
+
+    /// Marks every visible turtle on the image
+    pub fn show(&self, image: &mut Image) {
+        for turtle in self.turtles.values() {
+            turtle.show(image);
+        }
+    }
+
+    /// Starts recording the outlines of the active turtles for a `filled` block
+    pub fn begin_outlines(&mut self) -> Outlines {
+        self.active
+            .iter()
+            .map(|&id| (id, self.turtles.get_mut(&id).unwrap().begin_outline()))
+            .collect()
+    }
+
+    /// Executes a primitive token on every active turtle
+    pub fn execute(
+        &mut self,
+        token: &Token,
+        depth: usize,
+        image: &mut Image,
+    ) -> Result<(), LogoError> {
+        for id in &self.active {
+            let command = Command::evaluate(token, *id);
+            let turtle = self.turtles.get_mut(id).unwrap();
+            apply(turtle, &mut self.trace, depth, &command, image)?;
+        }
+        Ok(())
+    }
+
+    /// Applies an evaluated command to every active turtle
+    pub fn apply(
+        &mut self,
+        command: &Command,
+        depth: usize,
+        image: &mut Image,
+    ) -> Result<(), LogoError> {
+        for id in &self.active {
+            let turtle = self.turtles.get_mut(id).unwrap();
+            apply(turtle, &mut self.trace, depth, command, image)?;
+        }
+        Ok(())
+    }
+
+    /// Applies an evaluated command to one turtle
+    pub fn apply_to(
+        &mut self,
+        id: usize,
+        command: &Command,
+        depth: usize,
+        image: &mut Image,
+    ) -> Result<(), LogoError> {
+        let turtle = self.turtles.get_mut(&id).unwrap();
+        apply(turtle, &mut self.trace, depth, command, image)
+    }
+
+    /// Applies a command to all turtles. The boundary belongs to the drawing
+    /// area, so it is also kept for the turtles created later.
+    pub fn apply_all(
+        &mut self,
+        command: &Command,
+        depth: usize,
+        image: &mut Image,
+    ) -> Result<(), LogoError> {
+        if let Command::Boundary(boundary) = command {
+            self.boundary = *boundary;
+        }
+        for turtle in self.turtles.values_mut() {
+            apply(turtle, &mut self.trace, depth, command, image)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,454 @@
+use std::rc::Rc;
+
+use crate::interpreter::bytecode::*;
+use crate::interpreter::image::*;
+use crate::interpreter::limits::*;
+use crate::interpreter::profile::*;
+use crate::interpreter::trace::*;
+use crate::interpreter::turtle::*;
+use crate::interpreter::turtles::*;
+use crate::interpreter::*;
+
+/// Blocks opened by the running instructions
+enum Block {
+    /// Iterations left after the current one
+    Repeat(u64),
+    /// Turtles running the instructions one after the other
+    Each(Vec<usize>, usize),
+    /// Turtles that were active before `ask`
+    Ask(Vec<usize>),
+    /// Outlines of the enclosing `filled` block and the colors to fill with,
+    /// with the origin of the fills when the image is tagged
+    Filled(Outlines, Vec<String>, Option<Origin>),
+}
+
+struct Frame {
+    /// Address to return to
+    ret: usize,
+    /// Position of the first input on the stack
+    base: usize,
+    /// Blocks opened before the call
+    blocks: usize,
+    /// Index of the running procedure in `Program::procedures`
+    procedure: usize,
+}
+
+/// Stack machine running a compiled program, which is given to each step
+pub struct Vm {
+    turtles: Turtles,
+    usage: Usage,
+    pc: usize,
+    stack: Vec<f64>,
+    /// Colors and label texts, kept apart from the numbers
+    words: Vec<Rc<str>>,
+    frames: Vec<Frame>,
+    blocks: Vec<Block>,
+    profile: Option<Profile>,
+}
+
+impl Vm {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            turtles: Turtles::new(),
+            usage: Usage::new(limits),
+            pc: 0,
+            stack: vec![],
+            words: vec![],
+            frames: vec![],
+            blocks: vec![],
+            profile: None,
+        }
+    }
+
+    pub fn set_trace(&mut self, trace: Trace) {
+        self.turtles.set_trace(trace);
+    }
+
+    /// Counts the work done from now on into the profile
+    pub fn set_profile(&mut self, profile: Profile) {
+        self.profile = Some(profile);
+    }
+
+    pub fn take_profile(&mut self) -> Option<Profile> {
+        self.profile.take()
+    }
+
+    /// Marks every visible turtle on the image
+    pub fn show(&self, image: &mut Image) {
+        self.turtles.show(image);
+    }
+
+    /// State of every turtle, by number
+    pub fn turtles(&self) -> Vec<TurtleState> {
+        self.turtles.states()
+    }
+
+    /// Runs the program to completion, keeping the drawing made up to an error
+    pub fn run(&mut self, program: &Program, image: &mut Image) -> Result<(), LogoError> {
+        loop {
+            match self.step(program, image) {
+                Ok(true) => {}
+                Ok(false) => return Ok(()),
+                Err(error) => {
+                    self.abandon(image);
+                    return Err(error);
+                }
+            }
+        }
+    }
+
+    /// Closes the blocks still open after an error so that partial fills are drawn
+    pub fn abandon(&mut self, image: &mut Image) {
+        self.unwind(0, image);
+    }
+
+    fn number(&mut self) -> f64 {
+        self.stack.pop().expect("Expected a number on the stack")
+    }
+
+    fn word(&mut self) -> String {
+        self.words
+            .pop()
+            .expect("Expected a word on the stack")
+            .to_string()
+    }
+
+    fn numbers(&mut self, count: usize) -> Vec<f64> {
+        let mut numbers: Vec<f64> = (0..count).map(|_| self.number()).collect();
+        numbers.reverse();
+        numbers
+    }
+
+    /// The turtle running the current instruction, when inside an `Each` loop
+    fn current(&self) -> Option<usize> {
+        match self.blocks.last() {
+            Some(Block::Each(ids, index)) => Some(ids[*index]),
+            _ => None,
+        }
+    }
+
+    fn who(&self) -> usize {
+        self.current().unwrap_or_else(|| self.turtles.who())
+    }
+
+    /// Closes the blocks opened above the given height
+    fn unwind(&mut self, height: usize, image: &mut Image) {
+        while self.blocks.len() > height {
+            match self.blocks.pop().unwrap() {
+                Block::Ask(outer) => {
+                    self.turtles.set_active(outer);
+                }
+                Block::Filled(outlines, colors, origin) => {
+                    let count = image.element_count();
+                    if let Some(origin) = &origin {
+                        image.set_origin(origin.clone());
+                    }
+                    for ((id, outer), fill) in outlines.into_iter().zip(colors) {
+                        self.turtles.get_mut(id).end_outline(outer, fill, image);
+                    }
+                    if let (Some(profile), Some(origin)) = (&mut self.profile, &origin) {
+                        profile.record(origin, 0, image.measure(count));
+                    }
+                }
+                Block::Repeat(_) | Block::Each(..) => {}
+            }
+        }
+    }
+
+    fn primitive(&mut self, primitive: Primitive) -> Command {
+        match primitive {
+            Primitive::Forward => Command::Forward(self.number()),
+            Primitive::Back => Command::Back(self.number()),
+            Primitive::TurnRight => Command::TurnRight(self.number()),
+            Primitive::TurnLeft => Command::TurnLeft(self.number()),
+            Primitive::Arc => {
+                let radius = self.number();
+                Command::Arc(self.number(), radius)
+            }
+            Primitive::Circle => Command::Circle(self.number()),
+            Primitive::Ellipse => {
+                let height = self.number();
+                Command::Ellipse(self.number(), height)
+            }
+            Primitive::SetFillColor => Command::SetFillColor(self.word()),
+            Primitive::Fill => Command::Fill,
+            Primitive::Label => Command::Label(self.word()),
+            Primitive::SetLabelHeight => Command::SetLabelHeight(self.number()),
+            Primitive::SetLabelFont => Command::SetLabelFont(self.word()),
+            Primitive::SetPenColor => Command::SetPenColor(self.word()),
+            Primitive::SetPenSize => Command::SetPenSize(self.number()),
+            Primitive::PenUp => Command::PenUp,
+            Primitive::PenDown => Command::PenDown,
+            Primitive::ShowTurtle => Command::ShowTurtle,
+            Primitive::HideTurtle => Command::HideTurtle,
+        }
+    }
+
+    /// Instruction being run and the calls leading to it
+    fn origin(&self, program: &Program) -> Origin {
+        let calls = self.frames.iter().map(|frame| {
+            let procedure = &program.procedures[frame.procedure];
+            (procedure.name.clone(), program.spans[frame.ret - 1])
+        });
+        Origin {
+            span: program.spans[self.pc - 1],
+            calls: calls.collect(),
+        }
+    }
+
+    /// Whether the origin of the drawing is needed, to tag the image or to
+    /// profile the program
+    fn tracks_origins(&self, image: &Image) -> bool {
+        image.tagged() || self.profile.is_some()
+    }
+
+    /// Counts a call made by the instruction being run, when profiling
+    fn profile_call(&mut self, program: &Program, index: usize) {
+        if let Some(mut profile) = self.profile.take() {
+            profile.call(&program.procedures[index].name, &self.origin(program));
+            self.profile = Some(profile);
+        }
+    }
+
+    /// Applies a turtle command, tagging and profiling what it draws when
+    /// the origins are tracked
+    fn command(
+        &mut self,
+        program: &Program,
+        command: &Command,
+        image: &mut Image,
+    ) -> Result<(), LogoError> {
+        if !self.tracks_origins(image) {
+            return self.apply(command, image);
+        }
+        let origin = self.origin(program);
+        image.set_origin(origin.clone());
+        let count = image.element_count();
+        // An instruction run by each turtle in turn counts once, as it does
+        // when the turtles run it together
+        let primitives = match self.blocks.last() {
+            Some(Block::Each(_, index)) if *index > 0 => 0,
+            _ => 1,
+        };
+        self.apply(command, image)?;
+        if let Some(profile) = &mut self.profile {
+            profile.record(&origin, primitives, image.measure(count));
+        }
+        Ok(())
+    }
+
+    /// Applies a turtle command to the turtles it is meant for
+    fn apply(&mut self, command: &Command, image: &mut Image) -> Result<(), LogoError> {
+        let depth = self.usage.depth();
+        match (command, self.current()) {
+            // The boundary belongs to the drawing area, shared by all turtles
+            (Command::Boundary(_), _) => self.turtles.apply_all(command, depth, image),
+            (_, Some(id)) => self.turtles.apply_to(id, command, depth, image),
+            (_, None) => self.turtles.apply(command, depth, image),
+        }
+    }
+
+    fn binary(&mut self, op: fn(f64, f64) -> f64) {
+        let rhs = self.number();
+        let lhs = self.number();
+        self.stack.push(op(lhs, rhs));
+    }
+
+    /// Address of the next instruction
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    /// Procedures being run, outermost first: their index, the address they
+    /// return to and the stack from their first input on
+    pub fn calls(&self) -> impl Iterator<Item = (usize, usize, &[f64])> {
+        self.frames
+            .iter()
+            .map(|frame| (frame.procedure, frame.ret, &self.stack[frame.base..]))
+    }
+
+    /// The turtle the next instruction applies to, the first active one
+    /// outside of `Each` loops
+    pub fn turtle(&self) -> &Turtle {
+        self.turtles.get(self.who())
+    }
+
+    /// Executes the next instruction, returning `Ok(false)` once the program
+    /// has finished
+    #[inline]
+    pub fn step(&mut self, program: &Program, image: &mut Image) -> Result<bool, LogoError> {
+        let op = &program.code[self.pc];
+        self.pc += 1;
+
+        match op {
+            Op::Number(value) => self.stack.push(*value),
+            Op::Word(word) => self.words.push(Rc::clone(word)),
+            Op::Param(slot) => {
+                let base = self.frames.last().map_or(0, |frame| frame.base);
+                self.stack.push(self.stack[base + slot]);
+            }
+            Op::Item(slot) => {
+                let index = self.number();
+                let start = self.frames.last().unwrap().base + slot;
+                let count = self.stack[start] as usize;
+                let value = item(&self.stack[start + 1..start + 1 + count], index);
+                self.stack.push(value);
+            }
+            Op::Who => self.stack.push(self.who() as f64),
+            Op::Add => self.binary(|lhs, rhs| lhs + rhs),
+            Op::Sub => self.binary(|lhs, rhs| lhs - rhs),
+            Op::Mul => self.binary(|lhs, rhs| lhs * rhs),
+            Op::Div => self.binary(|lhs, rhs| lhs / rhs),
+            Op::Greater => self.binary(|lhs, rhs| (lhs > rhs) as u8 as f64),
+            Op::Less => self.binary(|lhs, rhs| (lhs < rhs) as u8 as f64),
+            Op::Palette => {
+                let index = self.number().max(0.0) as usize % PALETTE.len();
+                self.words.push(Rc::from(PALETTE[index]));
+            }
+            Op::Text => {
+                let text = self.number().to_string();
+                self.words.push(Rc::from(text));
+            }
+            Op::JumpUnless(address) => {
+                self.usage.step(image.element_count())?;
+                if self.number() == 0.0 {
+                    self.pc = *address;
+                }
+            }
+            Op::Repeat(address) => {
+                self.usage.step(image.element_count())?;
+                match self.number() as u64 {
+                    0 => self.pc = *address,
+                    times => self.blocks.push(Block::Repeat(times - 1)),
+                }
+            }
+            Op::Next(address) => {
+                if let Some(Block::Repeat(remaining)) = self.blocks.last_mut() {
+                    if *remaining > 0 {
+                        *remaining -= 1;
+                        self.pc = *address;
+                        // Empty bodies still count towards the limits
+                        self.usage.step(image.element_count())?;
+                        return Ok(true);
+                    }
+                }
+                self.blocks.pop();
+            }
+            Op::Each(address) => {
+                let ids = self.turtles.active().to_vec();
+                if ids.is_empty() {
+                    self.pc = *address;
+                } else {
+                    self.blocks.push(Block::Each(ids, 0));
+                }
+            }
+            Op::NextTurtle(address) => {
+                if let Some(Block::Each(ids, index)) = self.blocks.last_mut() {
+                    *index += 1;
+                    if *index < ids.len() {
+                        self.pc = *address;
+                        return Ok(true);
+                    }
+                }
+                self.blocks.pop();
+            }
+            Op::Call(index, slots) => {
+                self.usage.step(image.element_count())?;
+                self.usage.enter()?;
+                self.profile_call(program, *index);
+                let procedure = &program.procedures[*index];
+                self.frames.push(Frame {
+                    ret: self.pc,
+                    base: self.stack.len() - slots,
+                    blocks: self.blocks.len(),
+                    procedure: *index,
+                });
+                self.pc = procedure.entry;
+            }
+            Op::TailCall(index, slots) => {
+                self.usage.step(image.element_count())?;
+                self.profile_call(program, *index);
+                let procedure = &program.procedures[*index];
+                let frame = self.frames.last_mut().unwrap();
+                frame.procedure = *index;
+                let (base, blocks) = (frame.base, frame.blocks);
+                // Move the inputs of the call over the ones of the running procedure
+                let inputs = self.stack.len() - slots;
+                self.stack.drain(base..inputs);
+                self.unwind(blocks, image);
+                self.pc = procedure.entry;
+            }
+            Op::Return => {
+                let frame = self.frames.pop().unwrap();
+                self.unwind(frame.blocks, image);
+                self.stack.truncate(frame.base);
+                self.pc = frame.ret;
+                self.usage.leave();
+            }
+            Op::Break(address) => {
+                self.usage.step(image.element_count())?;
+                self.unwind(0, image);
+                self.pc = *address;
+            }
+            Op::Tell(count) => {
+                self.usage.step(image.element_count())?;
+                let ids = self.numbers(*count);
+                let ids = self.turtles.select(&ids);
+                self.turtles.set_active(ids);
+            }
+            Op::Ask(count) => {
+                self.usage.step(image.element_count())?;
+                let ids = self.numbers(*count);
+                let ids = self.turtles.select(&ids);
+                let outer = self.turtles.set_active(ids);
+                self.blocks.push(Block::Ask(outer));
+            }
+            Op::EndAsk | Op::EndFilled => {
+                let height = self.blocks.len() - 1;
+                self.unwind(height, image);
+            }
+            Op::Filled => {
+                self.usage.step(image.element_count())?;
+                let origin = self.tracks_origins(image).then(|| self.origin(program));
+                self.blocks.push(Block::Filled(vec![], vec![], origin));
+            }
+            Op::Outline => {
+                let color = self.word();
+                let ids = match self.current() {
+                    Some(id) => vec![id],
+                    None => self.turtles.active().to_vec(),
+                };
+                let outlines: Vec<_> = ids
+                    .into_iter()
+                    .map(|id| (id, self.turtles.get_mut(id).begin_outline()))
+                    .collect();
+                let filled = self.blocks.iter_mut().rev().find_map(|block| match block {
+                    Block::Filled(outlines, colors, _) => Some((outlines, colors)),
+                    _ => None,
+                });
+                let (filled_outlines, colors) = filled.unwrap();
+                colors.extend(outlines.iter().map(|_| color.clone()));
+                filled_outlines.extend(outlines);
+            }
+            Op::NewTurtle => {
+                self.usage.step(image.element_count())?;
+                self.turtles.new_turtle();
+            }
+            Op::Boundary(boundary) => {
+                self.usage.step(image.element_count())?;
+                self.command(program, &Command::Boundary(*boundary), image)?;
+            }
+            Op::Primitive(primitive) => {
+                self.usage.step(image.element_count())?;
+                let command = self.primitive(*primitive);
+                self.command(program, &command, image)?;
+            }
+            Op::Halt => {
+                self.pc -= 1;
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+}
@@ -0,0 +1,332 @@
+use std::rc::Rc;
+
+use crate::interpreter::image::*;
+use crate::interpreter::limits::*;
+use crate::interpreter::profile::*;
+use crate::interpreter::trace::*;
+use crate::interpreter::turtle::*;
+use crate::interpreter::turtles::*;
+use crate::interpreter::*;
+
+/// What to do when the instructions of a frame run out
+enum FrameKind {
+    Program,
+    Block,
+    /// Iterations left after the current one
+    Repeat(u64),
+    /// Procedure being run
+    Call(String),
+    /// Turtles that were active before `ask`
+    Ask(Vec<usize>),
+    /// Color, outlines of the enclosing block and the origin of the fills
+    /// when the image is tagged
+    Filled(Color, Outlines, Option<Origin>),
+}
+
+/// A block of instructions being executed
+struct Frame {
+    tokens: Rc<Vec<Token>>,
+    pc: usize,
+    kind: FrameKind,
+    /// Instruction being run in the frame
+    source: Span,
+}
+
+impl Frame {
+    fn new(tokens: Vec<Token>, kind: FrameKind) -> Self {
+        Self {
+            tokens: Rc::new(tokens),
+            pc: 0,
+            kind,
+            source: Span::default(),
+        }
+    }
+}
+
+/// Reference interpreter walking the parsed tokens, parameters are substituted
+/// into a copy of the procedure body at each call
+pub struct Walker {
+    turtles: Turtles,
+    usage: Usage,
+    frames: Vec<Frame>,
+    profile: Option<Profile>,
+}
+
+impl Walker {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            turtles: Turtles::new(),
+            usage: Usage::new(limits),
+            frames: vec![],
+            profile: None,
+        }
+    }
+
+    pub fn set_trace(&mut self, trace: Trace) {
+        self.turtles.set_trace(trace);
+    }
+
+    /// Counts the work done from now on into the profile
+    pub fn set_profile(&mut self, profile: Profile) {
+        self.profile = Some(profile);
+    }
+
+    pub fn take_profile(&mut self) -> Option<Profile> {
+        self.profile.take()
+    }
+
+    /// Marks every visible turtle on the image
+    pub fn show(&self, image: &mut Image) {
+        self.turtles.show(image);
+    }
+
+    /// State of every turtle, by number
+    pub fn turtles(&self) -> Vec<TurtleState> {
+        self.turtles.states()
+    }
+
+    /// Evaluates turtle numbers, creating the turtles that do not exist yet
+    fn select(&mut self, ids: &[Expr]) -> Vec<usize> {
+        let who = self.turtles.who();
+        let ids: Vec<f64> = ids.iter().map(|id| id.evaluate(who)).collect();
+        self.turtles.select(&ids)
+    }
+
+    /// Starts running a program from its first command
+    pub fn start(&mut self, commands: Vec<Token>) {
+        self.frames.clear();
+        self.frames.push(Frame::new(commands, FrameKind::Program));
+    }
+
+    /// Runs the program to completion, keeping the drawing made up to an error
+    pub fn execute(
+        &mut self,
+        commands: Vec<Token>,
+        image: &mut Image,
+        fns: &Functions,
+    ) -> Result<(), LogoError> {
+        self.start(commands);
+        loop {
+            match self.step(image, fns) {
+                Ok(true) => {}
+                Ok(false) => return Ok(()),
+                Err(error) => {
+                    // Close the blocks still open so that partial fills are drawn
+                    while !self.frames.is_empty() {
+                        self.finish(image);
+                    }
+                    return Err(error);
+                }
+            }
+        }
+    }
+
+    /// Pops the innermost frame, undoing what entering it changed
+    fn finish(&mut self, image: &mut Image) {
+        let frame = self.frames.pop().unwrap();
+        match frame.kind {
+            FrameKind::Call(_) => self.usage.leave(),
+            FrameKind::Ask(outer) => {
+                self.turtles.set_active(outer);
+            }
+            FrameKind::Filled(color, outer, origin) => {
+                let count = image.element_count();
+                if let Some(origin) = &origin {
+                    image.set_origin(origin.clone());
+                }
+                for (id, outer) in outer {
+                    let fill = color.evaluate(id);
+                    self.turtles.get_mut(id).end_outline(outer, fill, image);
+                }
+                if let (Some(profile), Some(origin)) = (&mut self.profile, &origin) {
+                    profile.record(origin, 0, image.measure(count));
+                }
+            }
+            FrameKind::Program | FrameKind::Block | FrameKind::Repeat(_) => {}
+        }
+    }
+
+    /// Leaves the blocks up to the procedure being run. Outside of procedures
+    /// only the current top level command is abandoned.
+    fn stop(&mut self, image: &mut Image) {
+        while let Some(frame) = self.frames.last() {
+            if let FrameKind::Program = frame.kind {
+                break;
+            }
+            let call = matches!(frame.kind, FrameKind::Call(_));
+            self.finish(image);
+            if call {
+                break;
+            }
+        }
+    }
+
+    /// Before a call in tail position, drops the procedure frame it would
+    /// return into, along with the finished `if` and bracket blocks around the
+    /// call, so that recursion in tail position runs in constant space
+    fn leave_tail_call(&mut self, image: &mut Image) {
+        for (i, frame) in self.frames.iter().enumerate().rev() {
+            if !no_instructions(&frame.tokens[frame.pc..]) {
+                return;
+            }
+            match frame.kind {
+                FrameKind::Block => {}
+                FrameKind::Call(_) => {
+                    while self.frames.len() > i {
+                        self.finish(image);
+                    }
+                    return;
+                }
+                _ => return,
+            }
+        }
+    }
+
+    /// Executes the next instruction, returning `Ok(false)` once the program
+    /// has finished. Blocks and procedure calls push frames on a heap allocated
+    /// stack, so deep recursion is only bounded by the configured limits.
+    pub fn step(&mut self, image: &mut Image, fns: &Functions) -> Result<bool, LogoError> {
+        let Some(frame) = self.frames.last_mut() else {
+            return Ok(false);
+        };
+        if frame.pc == frame.tokens.len() {
+            if let FrameKind::Repeat(remaining) = &mut frame.kind {
+                if *remaining > 0 {
+                    *remaining -= 1;
+                    frame.pc = 0;
+                    // Empty bodies still count towards the limits
+                    self.usage.step(image.element_count())?;
+                    return Ok(true);
+                }
+            }
+            self.finish(image);
+            return Ok(true);
+        }
+        let tokens = Rc::clone(&frame.tokens);
+        let token = &tokens[frame.pc];
+        frame.pc += 1;
+
+        match token {
+            Token::Source(span) => {
+                frame.source = *span;
+                return Ok(true);
+            }
+            Token::Comment(..) => return Ok(true),
+            _ => {}
+        }
+
+        self.usage.step(image.element_count())?;
+        match token {
+            Token::Repeat(expr, body) => {
+                let times = expr.evaluate(self.turtles.who()) as u64;
+                if times > 0 {
+                    self.push(body, FrameKind::Repeat(times - 1), "Repeat");
+                }
+            }
+            Token::FnCall(label, args) => {
+                if fns.contains(label) {
+                    // Made from the calling procedure, which a tail call leaves
+                    let origin = self.profile.is_some().then(|| self.origin());
+                    self.leave_tail_call(image);
+                    self.usage.enter()?;
+                    if let (Some(profile), Some(origin)) = (&mut self.profile, &origin) {
+                        profile.call(label, origin);
+                    }
+                    let commands = fns.get_commands(label, args, self.turtles.who());
+                    let kind = FrameKind::Call(label.clone());
+                    self.frames.push(Frame::new(commands, kind));
+                } else {
+                    panic!("Function '{}' not found in the environment", label);
+                }
+            }
+            Token::Bracket(tokens) => {
+                self.frames
+                    .push(Frame::new(tokens.clone(), FrameKind::Block));
+            }
+            Token::If(log_expr, body) => {
+                if let LogExpr::Val(true) = log_expr.evaluate(self.turtles.who()) {
+                    self.push(body, FrameKind::Block, "If");
+                }
+            }
+            Token::Filled(color, body) => {
+                let outer = self.turtles.begin_outlines();
+                let origin = self.tracks_origins(image).then(|| self.origin());
+                let kind = FrameKind::Filled(color.clone(), outer, origin);
+                self.push(body, kind, "Filled");
+            }
+            Token::Tell(ids) => {
+                let ids = self.select(ids);
+                self.turtles.set_active(ids);
+            }
+            Token::Ask(ids, body) => {
+                let asked = self.select(ids);
+                let outer = self.turtles.set_active(asked);
+                self.push(body, FrameKind::Ask(outer), "Ask");
+            }
+            Token::NewTurtle => {
+                self.turtles.new_turtle();
+            }
+            Token::Stop => {
+                self.stop(image);
+            }
+            _ => self.primitive(token, image)?,
+        }
+
+        Ok(true)
+    }
+
+    /// Whether the origin of the drawing is needed, to tag the image or to
+    /// profile the program
+    fn tracks_origins(&self, image: &Image) -> bool {
+        image.tagged() || self.profile.is_some()
+    }
+
+    /// Runs a turtle primitive on the turtles it is meant for
+    fn primitive(&mut self, token: &Token, image: &mut Image) -> Result<(), LogoError> {
+        let origin = self.tracks_origins(image).then(|| self.origin());
+        if let Some(origin) = &origin {
+            image.set_origin(origin.clone());
+        }
+        let count = image.element_count();
+        let depth = self.usage.depth();
+        match token {
+            // The boundary belongs to the drawing area, shared by all turtles
+            Token::Wrap | Token::Window | Token::Fence => {
+                let command = Command::evaluate(token, 0);
+                self.turtles.apply_all(&command, depth, image)?;
+            }
+            _ => self.turtles.execute(token, depth, image)?,
+        }
+        if let (Some(profile), Some(origin)) = (&mut self.profile, &origin) {
+            profile.record(origin, 1, image.measure(count));
+        }
+        Ok(())
+    }
+
+    /// Instruction being run and the calls leading to it, each made from
+    /// the instruction of the frame below
+    fn origin(&self) -> Origin {
+        let calls = self
+            .frames
+            .windows(2)
+            .filter_map(|pair| match &pair[1].kind {
+                FrameKind::Call(label) => Some((label.clone(), pair[0].source)),
+                _ => None,
+            });
+        Origin {
+            span: self
+                .frames
+                .last()
+                .map_or(Span::default(), |frame| frame.source),
+            calls: calls.collect(),
+        }
+    }
+
+    /// Enters the bracketed body of a control command
+    fn push(&mut self, body: &Token, kind: FrameKind, name: &str) {
+        match body {
+            Token::Bracket(tokens) => self.frames.push(Frame::new(tokens.clone(), kind)),
+            _ => panic!("{} body must be a Bracket token", name),
+        }
+    }
+}
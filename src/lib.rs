@@ -0,0 +1,2 @@
+mod interpreter;
+pub use interpreter::*;
@@ -1,12 +1,295 @@
-mod interpreter;
-use interpreter::*;
+use logo_interpreter::*;
+
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::str::FromStr;
+use std::time::Duration;
+
+fn usage() -> ! {
+    eprintln!(
+        "Usage: logo_interpreter [program.logo] [output.svg] [--precision N] [--animate SPEED [--sprite]]"
+    );
+    eprintln!(
+        "       [--max-instructions N] [--max-depth N] [--max-segments N] [--timeout SECONDS]"
+    );
+    eprintln!("       [--trace trace.jsonl] [--tag-origins] [--profile stacks.folded]");
+    eprintln!("       logo_interpreter check program.logo");
+    eprintln!("       logo_interpreter fmt program.logo [--write]");
+    eprintln!("       logo_interpreter debug program.logo [output.svg]");
+    std::process::exit(2);
+}
+
+fn value<T: FromStr>(value: Option<String>) -> T {
+    match value.map(|value| value.parse()) {
+        Some(Ok(value)) => value,
+        _ => usage(),
+    }
+}
+
+fn read_source(path: &str) -> String {
+    fs::read_to_string(path).expect("Unable to read source file")
+}
+
+/// Reports the problems found in a program without running it, failing when
+/// some of them are errors
+fn check_program(path: &str) -> ! {
+    let input = read_source(path);
+    let diagnostics = check(&input);
+    for diagnostic in &diagnostics {
+        let (line, column) = diagnostic.span.position(&input);
+        println!(
+            "{}:{}:{}: {}: {}",
+            path, line, column, diagnostic.severity, diagnostic.message
+        );
+    }
+    let failed = diagnostics
+        .iter()
+        .any(|diagnostic| diagnostic.severity == Severity::Error);
+    std::process::exit(failed as i32);
+}
+
+/// Prints a program in the canonical layout, or rewrites the file with it
+fn format_program(path: &str, write: bool) -> ! {
+    let input = read_source(path);
+    match format(&input) {
+        Ok(formatted) if write => fs::write(path, formatted).expect("Unable to write source file"),
+        Ok(formatted) => print!("{}", formatted),
+        Err(error) => {
+            let (line, column) = error.span.position(&input);
+            eprintln!("{}:{}:{}: error: {}", path, line, column, error);
+            std::process::exit(1);
+        }
+    }
+    std::process::exit(0);
+}
+
+const DEBUG_HELP: &str = "Commands:
+  break LINE|PROCEDURE   pause before a line or on entry to a procedure
+  delete LINE|PROCEDURE  remove a breakpoint
+  step                   run the next instruction, entering procedures
+  next                   run the next instruction and the procedures it calls
+  out                    run until the current procedure returns
+  continue               run until a breakpoint or the end
+  stack                  show the procedures being run and their inputs
+  turtle                 show the position and heading of the turtle
+  quit                   stop debugging and save the drawing";
+
+/// Line and first line of text of an instruction
+fn describe(input: &str, span: Span) -> String {
+    let (line, _) = span.position(input);
+    let text = input[span.start..span.end]
+        .lines()
+        .next()
+        .unwrap_or_default();
+    format!("line {}: {}", line, text)
+}
+
+/// Number to two decimal places, without a negative zero
+fn rounded(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0 + 0.0
+}
+
+fn breakpoint(arg: &str) -> Breakpoint {
+    match arg.parse() {
+        Ok(line) => Breakpoint::Line(line),
+        Err(_) => Breakpoint::Procedure(arg.to_string()),
+    }
+}
+
+/// Runs a program under the debugger, taking commands from the standard input
+fn debug_program(path: &str, image_path: &str) -> ! {
+    let input = read_source(path);
+    let mut debugger = match Debugger::new(&input, &Options::default()) {
+        Ok(debugger) => debugger,
+        Err(error) => {
+            let (line, column) = error.span.position(&input);
+            eprintln!("{}:{}:{}: error: {}", path, line, column, error);
+            std::process::exit(1);
+        }
+    };
+
+    println!("{}", DEBUG_HELP);
+    if let Some(span) = debugger.span() {
+        println!("Paused at {}", describe(&input, span));
+    }
+    let mut lines = io::stdin().lock().lines();
+    loop {
+        print!("(debug) ");
+        io::stdout().flush().unwrap();
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            _ => break,
+        };
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let pause = match words.as_slice() {
+            [] => continue,
+            ["break" | "b", arg] => {
+                debugger.add_breakpoint(breakpoint(arg));
+                continue;
+            }
+            ["delete" | "d", arg] => {
+                if !debugger.remove_breakpoint(&breakpoint(arg)) {
+                    println!("No breakpoint at {}", arg);
+                }
+                continue;
+            }
+            ["step" | "s"] => debugger.step_in(),
+            ["next" | "n"] => debugger.step_over(),
+            ["out" | "o"] => debugger.step_out(),
+            ["continue" | "c"] => debugger.resume(),
+            ["stack" | "bt"] => {
+                for (i, frame) in debugger.stack().iter().enumerate() {
+                    let mut inputs: Vec<String> = frame
+                        .inputs
+                        .iter()
+                        .map(|(name, value)| format!("{} = {}", name, value))
+                        .collect();
+                    if let Some((name, values)) = &frame.rest {
+                        let values: Vec<String> = values.iter().map(f64::to_string).collect();
+                        inputs.push(format!("{} = [{}]", name, values.join(" ")));
+                    }
+                    let procedure = frame.procedure.as_deref().unwrap_or("top level");
+                    let at = describe(&input, frame.span);
+                    match inputs.is_empty() {
+                        true => println!("#{} {} at {}", i, procedure, at),
+                        false => println!("#{} {} {} at {}", i, procedure, inputs.join(", "), at),
+                    }
+                }
+                continue;
+            }
+            ["turtle" | "t"] => {
+                let turtle = debugger.turtle();
+                let pen = match turtle.pen_down {
+                    true => "down",
+                    false => "up",
+                };
+                println!(
+                    "Turtle {} at ({}, {}) heading {}, pen {} in {} of size {}",
+                    turtle.id,
+                    rounded(turtle.x),
+                    rounded(turtle.y),
+                    rounded(turtle.heading),
+                    pen,
+                    turtle.pen_color,
+                    turtle.pen_size
+                );
+                continue;
+            }
+            ["quit" | "q"] => break,
+            _ => {
+                println!("{}", DEBUG_HELP);
+                continue;
+            }
+        };
+
+        match pause {
+            Pause::Breakpoint(Breakpoint::Line(line)) => println!("Breakpoint at line {}", line),
+            Pause::Breakpoint(Breakpoint::Procedure(name)) => println!("Breakpoint in {}", name),
+            Pause::Step => {}
+            Pause::Finished(Ok(())) => println!("Program finished"),
+            Pause::Finished(Err(error)) => println!("Error: {}", error),
+        }
+        if let Some(span) = debugger.span() {
+            println!("Paused at {}", describe(&input, span));
+        }
+    }
+
+    if let Err(error) = debugger.save(image_path) {
+        eprintln!("Error: {}", error);
+        std::process::exit(1);
+    }
+    println!("Image saved to {}", image_path);
+    std::process::exit(0);
+}
 
 fn main() {
-    let input = "repeat 2 [ forward 50 turn 90 ] forward 30";
-    let image_path = "img/output.svg";
+    let mut paths = vec![];
+    let mut options = Options::default();
+    let mut profile_path: Option<String> = None;
 
-    run(input, image_path);
-    println!("SVG file saved to {}", image_path);
+    let mut args = env::args().skip(1).peekable();
+    match args.peek().map(String::as_str) {
+        Some("check") => match (args.nth(1), args.next()) {
+            (Some(path), None) => check_program(&path),
+            _ => usage(),
+        },
+        Some("debug") => match (args.nth(1), args.next(), args.next()) {
+            (Some(path), image_path, None) => {
+                debug_program(&path, image_path.as_deref().unwrap_or("img/output.svg"))
+            }
+            _ => usage(),
+        },
+        Some("fmt") => match (args.nth(1), args.next().as_deref(), args.next()) {
+            (Some(path), None, None) => format_program(&path, false),
+            (Some(path), Some("--write"), None) => format_program(&path, true),
+            _ => usage(),
+        },
+        _ => {}
+    }
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--precision" => options.precision = value(args.next()),
+            "--animate" => {
+                let speed: f64 = value(args.next());
+                if !speed.is_finite() || speed <= 0.0 {
+                    usage();
+                }
+                options.animation = Some(Animation {
+                    speed,
+                    sprite: false,
+                });
+            }
+            "--sprite" => match options.animation.as_mut() {
+                Some(animation) => animation.sprite = true,
+                None => usage(),
+            },
+            "--max-instructions" => options.limits.instructions = Some(value(args.next())),
+            "--max-depth" => options.limits.depth = Some(value(args.next())),
+            "--max-segments" => options.limits.segments = Some(value(args.next())),
+            "--trace" => options.trace = Some(value(args.next())),
+            "--tag-origins" => options.tag_origins = true,
+            "--profile" => profile_path = Some(value(args.next())),
+            "--timeout" => match Duration::try_from_secs_f64(value(args.next())) {
+                Ok(time) => options.limits.time = Some(time),
+                Err(_) => usage(),
+            },
+            flag if flag.starts_with("--") => usage(),
+            _ => paths.push(arg),
+        }
+    }
+
+    let input = match paths.first() {
+        Some(source_path) => read_source(source_path),
+        None => String::from("repeat 2 [ forward 50 turn 90 ] forward 30"),
+    };
+    let image_path = paths.get(1).map_or("img/output.svg", |path| path.as_str());
+
+    let result = match profile_path {
+        Some(profile_path) => {
+            let (profile, result) = run_profiled(&input, image_path, &options);
+            print!("{}", profile.table());
+            fs::write(&profile_path, profile.folded()).expect("Unable to write profile file");
+            println!("Stacks saved to {}", profile_path);
+            result
+        }
+        None => run_with_options(&input, image_path, &options),
+    };
+    if !matches!(result, Err(LogoError::Io(_))) {
+        println!("Image saved to {}", image_path);
+    }
+    match result {
+        Err(LogoError::Parse(error)) => {
+            let (line, column) = error.span.position(&input);
+            eprintln!("Error at line {}, column {}: {}", line, column, error);
+            std::process::exit(1);
+        }
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            std::process::exit(1);
+        }
+        Ok(()) => {}
+    }
 }
 
 #[cfg(test)]
@@ -18,7 +301,7 @@ mod tests {
         let input = "repeat 2 [ forward 50 turn 90 ] forward 30";
         let image_path = "img/two_angles.svg";
 
-        run(input, image_path);
+        run(input, image_path).unwrap();
     }
 
     #[test]
@@ -29,7 +312,7 @@ mod tests {
         star";
         let image_path = "img/star.svg";
 
-        run(input, image_path);
+        run(input, image_path).unwrap();
     }
 
     #[test]
@@ -38,7 +321,7 @@ mod tests {
             repeat 36 [  square 100 turn 10 ]";
         let image_path = "img/square.svg";
 
-        run(input, image_path);
+        run(input, image_path).unwrap();
     }
 
     #[test]
@@ -57,7 +340,7 @@ mod tests {
             tree 150";
         let image_path = "img/tree.svg";
 
-        run(input, image_path);
+        run(input, image_path).unwrap();
     }
 
     #[test]
@@ -74,6 +357,1106 @@ mod tests {
             fern 25 1";
         let image_path = "img/fern.svg";
 
-        run(input, image_path);
+        run(input, image_path).unwrap();
+    }
+
+    #[test]
+    fn test_square_merges_segments() {
+        let input = "to square :length repeat 4 [ forward :length turn 90 ] end\n
+            repeat 36 [  square 100 turn 10 ]";
+        let image_path = "img/square_merged.svg";
+
+        run(input, image_path).unwrap();
+        let svg = std::fs::read_to_string(image_path).unwrap();
+        assert_eq!(svg.matches("fill=\"none\"").count(), 1);
+        assert!(!svg.contains("<line"));
+    }
+
+    #[test]
+    fn test_precision() {
+        let input = "turn 30 forward 100";
+        let image_path = "img/precision.svg";
+
+        let options = Options {
+            precision: 1,
+            ..Options::default()
+        };
+        run_with_options(input, image_path, &options).unwrap();
+        let svg = std::fs::read_to_string(image_path).unwrap();
+        assert!(svg.contains("d=\"M0 0 L50 -86.6\""));
+    }
+
+    #[test]
+    fn test_turtle_visibility() {
+        let image_path = "img/turtle_shown.svg";
+        run("forward 50 turn 90", image_path).unwrap();
+        let svg = std::fs::read_to_string(image_path).unwrap();
+        assert!(svg.contains("transform=\"translate(0 -50) rotate(0)\""));
+
+        let image_path = "img/turtle_hidden.svg";
+        run("forward 50 ht turn 90 st hideturtle", image_path).unwrap();
+        let svg = std::fs::read_to_string(image_path).unwrap();
+        assert!(!svg.contains("transform"));
+    }
+
+    #[test]
+    fn test_curves() {
+        let input = "arc 90 100 turn 90 forward 100 circle 50 ellipse 30 60";
+        let image_path = "img/curves.svg";
+
+        run(input, image_path).unwrap();
+        let svg = std::fs::read_to_string(image_path).unwrap();
+        assert!(svg.contains("d=\"M0 -100 A100 100 0 0 1 100 0\""));
+        assert!(svg.contains("d=\"M150 0 A50 50 0 0 1 50 0 A50 50 0 0 1 150 0\""));
+        assert!(svg.contains("d=\"M100 30 A30 60 90 0 1 100 -30 A30 60 90 0 1 100 30\""));
+        assert!(svg.contains("translate(100 0) rotate(0)"));
+    }
+
+    fn png_pixel(image_path: &str, x: usize, y: usize) -> [u8; 3] {
+        let decoder = png::Decoder::new(std::fs::File::open(image_path).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data).unwrap();
+        let offset = (y * info.width as usize + x) * 3;
+        [data[offset], data[offset + 1], data[offset + 2]]
+    }
+
+    #[test]
+    fn test_filled() {
+        let input = "setfillcolor 4 filled \"blue [ repeat 4 [ forward 100 turn 90 ] ] ht";
+
+        let image_path = "img/filled.svg";
+        run(input, image_path).unwrap();
+        let svg = std::fs::read_to_string(image_path).unwrap();
+        assert!(svg.contains("d=\"M0 0 L0 -100 L100 -100 L100 0 L0 0 L0 0 Z\" fill=\"blue\""));
+
+        let image_path = "img/filled.png";
+        run(input, image_path).unwrap();
+        assert_eq!(png_pixel(image_path, 450, 350), [0, 0, 255]);
+        assert_eq!(png_pixel(image_path, 350, 350), [255, 255, 255]);
+    }
+
+    #[test]
+    fn test_fill() {
+        let input = "repeat 4 [ forward 100 turn 90 ]
+            turn 45 penup forward 20 setfillcolor \"red fill pendown ht";
+        let image_path = "img/fill.png";
+
+        run(input, image_path).unwrap();
+        assert_eq!(png_pixel(image_path, 450, 350), [255, 0, 0]);
+        assert_eq!(png_pixel(image_path, 400, 350), [0, 0, 0]);
+        assert_eq!(png_pixel(image_path, 350, 350), [255, 255, 255]);
+    }
+
+    #[test]
+    fn test_label() {
+        let input = "label \"Hello setlabelheight 24 setlabelfont \"serif
+            turn 90 penup forward 100 label [ side 100 ] label 2 * 50";
+
+        let image_path = "img/label.svg";
+        run(input, image_path).unwrap();
+        let svg = std::fs::read_to_string(image_path).unwrap();
+        assert!(svg.contains("font-family=\"sans-serif\" font-size=\"12\" transform=\"rotate(0 0 0)\" x=\"0\" y=\"0\">\nHello"));
+        assert!(svg.contains("font-family=\"serif\" font-size=\"24\" transform=\"rotate(90 100 0)\" x=\"100\" y=\"0\">\nside 100"));
+        assert!(svg.contains(">\n100\n</text>"));
+
+        let image_path = "img/label.png";
+        run(input, image_path).unwrap();
+        // Stem of the H just above the starting point
+        assert_eq!(png_pixel(image_path, 400, 395), [0, 0, 0]);
+    }
+
+    #[test]
+    fn test_wrap() {
+        // Same split as jslogo: the line leaves on the right and comes back on the left
+        let input = "turn 90 forward 500 turn 90 forward 850";
+        let image_path = "img/wrap.svg";
+
+        run(input, image_path).unwrap();
+        let svg = std::fs::read_to_string(image_path).unwrap();
+        assert!(svg.contains("d=\"M0 0 L400 0\""));
+        assert!(svg.contains("d=\"M-400 0 L-300 0 L-300 400\""));
+        assert!(svg.contains("d=\"M-300 -400 L-300 50\""));
+    }
+
+    #[test]
+    fn test_window() {
+        let input = "window turn 90 forward 500";
+        let image_path = "img/window.svg";
+
+        run(input, image_path).unwrap();
+        let svg = std::fs::read_to_string(image_path).unwrap();
+        assert!(svg.contains("d=\"M0 0 L500 0\""));
+
+        // Only the part of a line near the canvas is rasterised
+        let image_path = "img/window_far.png";
+        run("window turn 90 forward 1e12 ht", image_path).unwrap();
+        assert_eq!(png_pixel(image_path, 600, 400), [0, 0, 0]);
+        assert_eq!(png_pixel(image_path, 600, 410), [255, 255, 255]);
+        // A pen wider than the canvas covers all of it
+        let image_path = "img/window_wide_pen.png";
+        run("setpensize 1e6 forward 1 ht", image_path).unwrap();
+        assert_eq!(png_pixel(image_path, 0, 0), [0, 0, 0]);
+        assert_eq!(png_pixel(image_path, 799, 799), [0, 0, 0]);
+    }
+
+    #[test]
+    fn test_fence() {
+        let input = "fence forward 100 turn 90 forward 500 forward 10";
+        let image_path = "img/fence.svg";
+
+        assert_eq!(run(input, image_path), Err(LogoError::OutOfBounds));
+        let svg = std::fs::read_to_string(image_path).unwrap();
+        assert!(svg.contains("d=\"M0 0 L0 -100\""));
+        assert!(svg.contains("translate(0 -100) rotate(0)"));
+    }
+
+    #[test]
+    fn test_not_finite() {
+        let input = "forward 100 turn 90 forward 1 / 0";
+        let image_path = "img/not_finite.svg";
+
+        assert_eq!(run(input, image_path), Err(LogoError::NotFinite));
+        let svg = std::fs::read_to_string(image_path).unwrap();
+        assert!(svg.contains("d=\"M0 0 L0 -100\""));
+
+        for input in [
+            "turn 1 / 0 forward 10",
+            "arc 90 1 / 0",
+            "arc 0 / 0 10",
+            "circle 0 - 1 / 0",
+            "ellipse 0 / 0 3",
+            "setlabelheight 1 / 0 label [a]",
+            "setpensize 0 / 0 forward 10",
+        ] {
+            let image_path = "img/not_finite_curve.svg";
+            assert_eq!(run(input, image_path), Err(LogoError::NotFinite));
+            let svg = std::fs::read_to_string(image_path).unwrap();
+            assert!(!svg.contains("NaN") && !svg.contains("inf"));
+        }
+    }
+
+    #[test]
+    fn test_turtles() {
+        let input =
+            "tell [0 1 2 3] ask 1 [ turn 90 ] ask 2 [ turn 180 setpc \"red ] ask 3 [ turn 270 ]
+            setpensize 2 forward 100
+            tell [4 5] forward who * 10
+            newturtle turn 45";
+        let image_path = "img/turtles.svg";
+
+        run(input, image_path).unwrap();
+        let svg = std::fs::read_to_string(image_path).unwrap();
+        assert!(svg.contains("d=\"M0 0 L0 -100\" fill=\"none\" stroke=\"black\" stroke-linejoin=\"round\" stroke-width=\"2\""));
+        assert!(svg.contains("d=\"M0 0 L0 100\" fill=\"none\" stroke=\"red\""));
+        assert!(svg.contains("d=\"M0 0 L100 0\""));
+        assert!(svg.contains("d=\"M0 0 L-100 0\""));
+        assert!(svg.contains("d=\"M0 0 L0 -40\""));
+        assert!(svg.contains("d=\"M0 0 L0 -50\""));
+        assert_eq!(svg.matches("<path d=\"M10 0 L-5 6 L-5 -6 Z\"").count(), 7);
+        assert!(svg.contains("translate(0 0) rotate(315)"));
+    }
+
+    #[test]
+    fn test_engines_agree() {
+        let inputs = [
+            "to tree :size\n
+            if :size < 5 [forward :size back :size stop]\n
+            forward :size/3\n
+            left 30 tree :size*2/3 right 30\n
+            forward :size/6\n
+            right 25 tree :size/2 left 25\n
+            forward :size/3\n
+            right 25 tree :size/2 left 25\n
+            forward :size/6\n
+            back :size\n
+            end\n
+            tree 150",
+            "tell [0 1 2] setpc who + 1 forward who * 20 + 10
+            filled who + 4 [ repeat 3 [ forward 30 turn 120 ] ]
+            ask [1 2] [ turn 90 label who arc 90 20 ] newturtle ellipse 20 10",
+            "repeat 3 [ forward 10 stop ] turn 90 forward 20 window forward 500",
+        ];
+        for input in inputs {
+            let mut svgs = vec![];
+            for (engine, image_path) in [
+                (Engine::Bytecode, "img/engine_bytecode.svg"),
+                (Engine::TreeWalk, "img/engine_tree_walk.svg"),
+            ] {
+                let options = Options {
+                    engine,
+                    ..Options::default()
+                };
+                run_with_options(input, image_path, &options).unwrap();
+                svgs.push(std::fs::read_to_string(image_path).unwrap());
+            }
+            assert_eq!(svgs[0], svgs[1], "engines differ on {}", input);
+        }
+    }
+
+    #[test]
+    fn test_optional_inputs() {
+        let input = "to poly :sides [:size 50] [:angle 360 / :sides]
+                repeat :sides [ forward :size turn :angle ]
+            end
+            to sides [:lengths]
+                repeat count :lengths [ forward item 1 :lengths turn 90 ]
+                forward item 5 :lengths
+            end
+            poly 4 turn 180 (poly 3 80) (poly 6 30 -60) (sides 10 20 30) sides";
+        let mut svgs = vec![];
+        for (engine, image_path) in [
+            (Engine::Bytecode, "img/optional_inputs.svg"),
+            (Engine::TreeWalk, "img/optional_inputs_tree_walk.svg"),
+        ] {
+            let options = Options {
+                engine,
+                ..Options::default()
+            };
+            run_with_options(input, image_path, &options).unwrap();
+            svgs.push(std::fs::read_to_string(image_path).unwrap());
+        }
+        assert_eq!(svgs[0], svgs[1]);
+        assert!(svgs[0].contains("d=\"M0 0 L0 -50 L50 -50 L50 0 L0 0 L0 80"));
+    }
+
+    #[test]
+    fn test_comments() {
+        let input = "; draws a square, the end ~
+                of this comment is on the next line
+            to square :side ; the side length
+                repeat 4 [ forward :side ; one side
+                    turn 90 ]
+            end
+            square ; inputs may follow comments
+                50
+            forward 10 ~
+                turn 90";
+        run(input, "img/comments.svg").unwrap();
+        run(
+            "to square :side repeat 4 [ forward :side turn 90 ] end square 50 forward 10 turn 90",
+            "img/comments_removed.svg",
+        )
+        .unwrap();
+        assert_eq!(
+            std::fs::read_to_string("img/comments.svg").unwrap(),
+            std::fs::read_to_string("img/comments_removed.svg").unwrap()
+        );
+
+        // Comments are kept in the parsed tokens
+        let (commands, fns) = parse(input).unwrap();
+        assert!(matches!(&commands[0], Token::Comment(text, _)
+            if text == " draws a square, the end ~\n                of this comment is on the next line"));
+        assert!(
+            matches!(&commands[3], Token::Comment(text, _) if text == " inputs may follow comments")
+        );
+        let square = fns.get(&String::from("square")).unwrap();
+        assert!(matches!(&square.body[0], Token::Comment(text, _) if text == " the side length"));
+
+        // Comments in a list are not part of it
+        let input = "label [a ; note\n b]";
+        let image_path = "img/comments_in_list.svg";
+        run(input, image_path).unwrap();
+        let svg = std::fs::read_to_string(image_path).unwrap();
+        assert!(svg.contains(">\na b\n</text>"));
+        let (commands, _) = parse(input).unwrap();
+        assert!(matches!(&commands[2], Token::Comment(text, _) if text == " note"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |input: &str| match execute(input, &Options::default()) {
+            Err(LogoError::Parse(error)) => (error.span.position(input), error.message),
+            other => panic!("Expected a parse error, got {:?}", other),
+        };
+
+        assert_eq!(
+            error("forward +"),
+            ((1, 9), String::from("Expected a value but found '+'"))
+        );
+        assert_eq!(
+            error("repeat 4 [ forward 10\nturn 90"),
+            ((1, 10), String::from("'[' is never closed"))
+        );
+        assert_eq!(
+            error("to f :a forward :a end\nf"),
+            (
+                (2, 1),
+                String::from("Function 'f' expected 1 arguments but 0 were provided")
+            )
+        );
+        assert_eq!(
+            error("forward 10\n  (f 1 2)"),
+            ((2, 3), String::from("Function 'f' is not defined"))
+        );
+        assert_eq!(
+            error("to f forward :size end"),
+            (
+                (1, 14),
+                String::from("Parameter ':size' is not an input of 'f'")
+            )
+        );
+    }
+
+    #[test]
+    fn test_check() {
+        let input = "to spiral :size [:angle 90]\n\
+            forward :size right 90\n\
+            spiral :size + 5\n\
+            end\n\
+            to square :side\n\
+            repeat 4 [ forward :side turn 90 ]\n\
+            stop\n\
+            forward :side\n\
+            end\n\
+            square 50 spiral 10";
+        let found: Vec<_> = check(input)
+            .into_iter()
+            .map(|diagnostic| {
+                let (line, column) = diagnostic.span.position(input);
+                (line, column, diagnostic.severity, diagnostic.message)
+            })
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (
+                    1,
+                    1,
+                    Severity::Warning,
+                    String::from("Input :angle of 'spiral' is never used")
+                ),
+                (
+                    3,
+                    1,
+                    Severity::Warning,
+                    String::from("'spiral' calls itself with no way to stop")
+                ),
+                (
+                    8,
+                    1,
+                    Severity::Warning,
+                    String::from("Instructions after stop are never run")
+                ),
+            ]
+        );
+
+        // A base case silences the warning
+        assert!(check("to f :n if :n < 1 [stop] f :n - 1 end f 5").is_empty());
+
+        // Procedures calling each other are followed around the cycle
+        let input = "to a b end\nto b a end\na";
+        let found: Vec<_> = check(input)
+            .into_iter()
+            .map(|diagnostic| (diagnostic.span.position(input), diagnostic.message))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (
+                    (1, 6),
+                    String::from("'a' calls itself through 'b' with no way to stop")
+                ),
+                (
+                    (2, 6),
+                    String::from("'b' calls itself through 'a' with no way to stop")
+                ),
+            ]
+        );
+        assert!(check("to a :n if :n < 1 [stop] b :n - 1 end to b :n a :n end a 3").is_empty());
+
+        // A body repeated a number of times given by an input may not run
+        assert!(check("to f :n repeat :n [ f :n - 1 ] end f 3").is_empty());
+        assert_eq!(check("to f repeat 2 [ f ] end f").len(), 1);
+
+        // Every undefined procedure is reported
+        let errors = check("forward 10 triangle 50\nhexagon 20 30");
+        assert_eq!(errors.len(), 2);
+        assert!(errors.iter().all(|error| error.severity == Severity::Error));
+        assert_eq!(errors[1].message, "Function 'hexagon' is not defined");
+        assert_eq!(
+            errors[1]
+                .span
+                .position("forward 10 triangle 50\nhexagon 20 30"),
+            (2, 1)
+        );
+    }
+
+    #[test]
+    fn test_format() {
+        let input = "; a square\nto square :side repeat 4 [ fd :side rt 90 ] end\n\
+            to flower [:petals 6] [:sizes] ; petals with growing sizes\n\
+            repeat :petals [ square item 1 :sizes turn 360 / :petals ]\n\
+            end\n\
+            setpc \"red pu   back 20 pd (flower 3 10 20)\n\
+            if 1 > 0 [ filled 4 [ circle 20 ] ] ; always\n\
+            tell [1 2] ask 1 [ label [two words] ]";
+        let formatted = format(input).unwrap();
+        assert_eq!(
+            formatted,
+            "; a square
+to square :side
+  repeat 4 [
+    forward :side
+    right 90
+  ]
+end
+
+to flower [:petals 6] [:sizes] ; petals with growing sizes
+  repeat :petals [
+    square item 1 :sizes
+    right 360 / :petals
+  ]
+end
+
+setpencolor \"red
+penup
+back 20
+pendown
+(flower 3 10 20)
+if 1 > 0 [
+  filled 4 [
+    circle 20
+  ]
+] ; always
+tell [1 2]
+ask 1 [
+  label [two words]
+]
+"
+        );
+        assert_eq!(format(&formatted).unwrap(), formatted);
+
+        // A comment in a list moves after the instruction
+        let formatted = format("label [a ; note\n b]").unwrap();
+        assert_eq!(formatted, "label [a b] ; note\n");
+        assert_eq!(format(&formatted).unwrap(), formatted);
+
+        // Comments within a header move after it
+        let formatted = format("to ; name\n f [:x ; default\n 100] forward :x end f").unwrap();
+        assert_eq!(
+            formatted,
+            "to f [:x 100] ; name\n  ; default\n  forward :x\nend\n\nf\n"
+        );
+        assert_eq!(format(&formatted).unwrap(), formatted);
+    }
+
+    #[test]
+    fn test_format_keeps_drawing() {
+        let programs = [
+            "to tree :size if :size < 5 [forward :size back :size stop] forward :size/3 \
+             left 30 tree :size*2/3 right 30 forward :size/6 right 25 tree :size/2 left 25 \
+             forward :size/3 right 25 tree :size/2 left 25 forward :size/6 back :size end tree 150",
+            "to square :length repeat 4 [ forward :length turn 90 ] end \
+             repeat 36 [ square 100 turn 10 ]",
+            "to spiral :size [:angle 91] [:rest] if :size > 200 [stop] forward :size + count :rest \
+             right :angle (spiral :size + 4 :angle 1 2) end spiral 1 (spiral 3 89)",
+            "newturtle tell [0 1] setpencolor who + 1 ask 1 [ right 90 ] forward 50 - 1 - 2 \
+             setpensize 3 * 2 / 4 arc 90 30 ellipse 20 10 wrap forward 500 window",
+            "; comments ~\n over lines\nforward 10 ; after\nhideturtle label \"hi \
+             setlabelheight 20 setlabelfont [Courier New] label 12",
+        ];
+        for (i, program) in programs.iter().enumerate() {
+            let formatted = format(program).unwrap();
+            let original = format!("img/format_{}.svg", i);
+            let reformatted = format!("img/format_{}_formatted.svg", i);
+            run(program, &original).unwrap();
+            run(&formatted, &reformatted).unwrap();
+            assert_eq!(
+                std::fs::read_to_string(&original).unwrap(),
+                std::fs::read_to_string(&reformatted).unwrap(),
+                "Formatting changed the drawing of {}",
+                formatted
+            );
+            assert_eq!(format(&formatted).unwrap(), formatted);
+        }
+    }
+
+    #[test]
+    fn test_debugger() {
+        let input = "to square :side
+  repeat 4 [ forward :side right 90 ]
+end
+to spiral :size
+  if :size > 30 [ stop ]
+  square :size
+  spiral :size + 10
+end
+penup forward 10 pendown
+spiral 10
+right 45
+";
+        let options = Options::default();
+        let mut debugger = Debugger::new(input, &options).unwrap();
+        let next = |debugger: &Debugger| {
+            let span = debugger.span().unwrap();
+            (debugger.line().unwrap(), &input[span.start..span.end])
+        };
+        assert_eq!(next(&debugger), (9, "penup"));
+        assert_eq!(debugger.step_over(), Pause::Step);
+        assert_eq!(next(&debugger), (9, "forward 10"));
+
+        let square = Breakpoint::Procedure(String::from("square"));
+        debugger.add_breakpoint(square.clone());
+        assert_eq!(debugger.resume(), Pause::Breakpoint(square.clone()));
+        assert_eq!(next(&debugger).0, 2);
+        let stack = debugger.stack();
+        let frames: Vec<_> = stack
+            .iter()
+            .map(|frame| (frame.procedure.as_deref(), frame.inputs.clone()))
+            .collect();
+        assert_eq!(
+            frames,
+            [
+                (Some("square"), vec![(String::from(":side"), 10.0)]),
+                (Some("spiral"), vec![(String::from(":size"), 10.0)]),
+                (None, vec![]),
+            ]
+        );
+        assert_eq!(
+            &input[stack[1].span.start..stack[1].span.end],
+            "square :size"
+        );
+        assert_eq!(&input[stack[2].span.start..stack[2].span.end], "spiral 10");
+        let turtle = debugger.turtle();
+        assert!(turtle.x.abs() < 1e-9 && turtle.y == 10.0 && turtle.heading == 0.0);
+        assert!(turtle.pen_down);
+
+        assert_eq!(debugger.step_in(), Pause::Step);
+        assert_eq!(next(&debugger), (2, "forward :side"));
+        assert_eq!(debugger.step_over(), Pause::Step);
+        assert_eq!(next(&debugger), (2, "right 90"));
+        assert_eq!(debugger.step_out(), Pause::Step);
+        assert_eq!(next(&debugger), (7, "spiral :size + 10"));
+        // The tail call takes the place of the running procedure
+        assert_eq!(debugger.step_in(), Pause::Step);
+        assert_eq!(next(&debugger).0, 5);
+        let stack = debugger.stack();
+        assert_eq!(stack.len(), 2);
+        assert_eq!(stack[0].inputs, [(String::from(":size"), 20.0)]);
+
+        assert!(debugger.remove_breakpoint(&square));
+        assert!(!debugger.remove_breakpoint(&square));
+        debugger.add_breakpoint(Breakpoint::Line(11));
+        assert_eq!(debugger.resume(), Pause::Breakpoint(Breakpoint::Line(11)));
+        assert_eq!(debugger.turtle().heading, 0.0);
+        assert_eq!(debugger.resume(), Pause::Finished(Ok(())));
+        assert!(debugger.finished());
+        assert_eq!(debugger.span(), None);
+        assert!(debugger.stack().is_empty());
+        assert_eq!(debugger.turtle().heading, 45.0);
+        assert_eq!(debugger.step_in(), Pause::Finished(Ok(())));
+
+        let mut debugger = Debugger::new("fence forward 500", &options).unwrap();
+        assert_eq!(
+            debugger.resume(),
+            Pause::Finished(Err(LogoError::OutOfBounds))
+        );
+        assert!(Debugger::new("forward", &options).is_err());
+    }
+
+    #[test]
+    fn test_trace() {
+        let input = "to corner :side forward :side right 90 end
+            setpencolor \"red corner 50 penup back 10";
+        let mut traces = vec![];
+        for (engine, name) in [
+            (Engine::Bytecode, "bytecode"),
+            (Engine::TreeWalk, "tree_walk"),
+        ] {
+            let trace_path = format!("img/trace_{}.jsonl", name);
+            let options = Options {
+                engine,
+                trace: Some(trace_path.clone()),
+                ..Options::default()
+            };
+            execute(input, &options).unwrap();
+            traces.push(std::fs::read_to_string(trace_path).unwrap());
+        }
+        assert_eq!(traces[0], traces[1]);
+
+        let lines: Vec<serde_json::Value> = traces[0]
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let primitives: Vec<_> = lines.iter().map(|line| &line["primitive"]).collect();
+        assert_eq!(
+            primitives,
+            ["setpencolor", "forward", "right", "penup", "back"]
+        );
+        assert_eq!(lines[0]["depth"], 0);
+        assert_eq!(lines[0]["inputs"], serde_json::json!(["red"]));
+        assert_eq!(lines[1]["depth"], 1);
+        assert_eq!(lines[1]["inputs"], serde_json::json!([50.0]));
+        assert_eq!(lines[1]["before"]["y"], 0.0);
+        assert_eq!(lines[1]["after"]["y"], 50.0);
+        assert_eq!(lines[1]["after"]["pen_color"], "red");
+        assert_eq!(lines[2]["after"]["heading"], 90.0);
+        assert_eq!(lines[3]["before"]["pen_down"], true);
+        assert_eq!(lines[3]["after"]["pen_down"], false);
+        assert!((lines[4]["after"]["x"].as_f64().unwrap() + 10.0).abs() < 1e-9);
+
+        // The command that fails is still traced
+        let options = Options {
+            trace: Some(String::from("img/trace_error.jsonl")),
+            ..Options::default()
+        };
+        assert!(execute("fence forward 500", &options).is_err());
+        let trace = std::fs::read_to_string("img/trace_error.jsonl").unwrap();
+        let last: serde_json::Value = serde_json::from_str(trace.lines().last().unwrap()).unwrap();
+        assert_eq!(last["error"], "Turtle out of bounds");
+
+        // A trace file that cannot be created stops the run instead of panicking
+        for engine in [Engine::Bytecode, Engine::TreeWalk] {
+            let options = Options {
+                engine,
+                trace: Some(String::from("img/missing/trace.jsonl")),
+                ..Options::default()
+            };
+            let result = execute("forward 10", &options);
+            assert!(matches!(result, Err(LogoError::Trace(_))));
+        }
+    }
+
+    #[test]
+    fn test_tag_origins() {
+        let input = "to square :side
+  repeat 4 [ forward :side right 90 ]
+end
+to flower :n
+  repeat :n [ square 50 right 360 / :n ]
+end
+filled \"red [ forward 30 right 90 forward 30 ]
+forward 10 back 10
+flower 3
+label \"done
+";
+        let mut outputs = vec![];
+        for (engine, name) in [
+            (Engine::Bytecode, "origins"),
+            (Engine::TreeWalk, "origins_tree_walk"),
+        ] {
+            let image_path = format!("img/{}.svg", name);
+            let options = Options {
+                engine,
+                tag_origins: true,
+                ..Options::default()
+            };
+            run_with_options(input, &image_path, &options).unwrap();
+            let svg = std::fs::read_to_string(&image_path).unwrap();
+            let origins = std::fs::read_to_string(image_path + ".json").unwrap();
+            outputs.push((svg, origins));
+        }
+        assert_eq!(outputs[0], outputs[1]);
+
+        // Lines drawn by different instructions are kept apart
+        let (svg, origins) = &outputs[0];
+        assert!(
+            svg.contains("d=\"M0 0 L0 -30 L30 -30 L0 0 Z\" data-span=\"116-162\" data-stack=\"\"")
+        );
+        assert!(svg.contains("d=\"M30 -30 L40 -30\" data-span=\"163-173\" data-stack=\"\""));
+        assert!(svg.contains("d=\"M40 -30 L30 -30\" data-span=\"174-181\""));
+        assert!(svg.contains("data-span=\"29-42\" data-stack=\"flower square\""));
+
+        let origins: serde_json::Value = serde_json::from_str(origins).unwrap();
+        let square = &origins[3];
+        assert_eq!(square["id"], "e3");
+        assert_eq!(square["span"]["line"], 2);
+        assert_eq!(square["span"]["column"], 14);
+        assert_eq!(square["stack"][0]["procedure"], "flower");
+        assert_eq!(square["stack"][0]["span"]["line"], 9);
+        assert_eq!(square["stack"][1]["procedure"], "square");
+        assert_eq!(square["stack"][1]["span"]["line"], 5);
+        assert_eq!(origins[4]["span"]["line"], 10);
+
+        // Files that cannot be written are reported instead of panicking
+        let options = Options {
+            tag_origins: true,
+            ..Options::default()
+        };
+        for path in ["img/missing/origins.svg", "img/missing/origins.png"] {
+            let result = run_with_options(input, path, &options);
+            assert!(matches!(result, Err(LogoError::Io(message)) if message.starts_with(path)));
+        }
+        // The image is saved, but a directory is in the way of its origins
+        std::fs::create_dir_all("target/origins_blocked.svg.json").unwrap();
+        let result = run_with_options(input, "target/origins_blocked.svg", &options);
+        assert!(matches!(result, Err(LogoError::Io(message))
+            if message.starts_with("target/origins_blocked.svg.json")));
+    }
+
+    #[test]
+    fn test_profile() {
+        let input = "to tree :size
+  if :size < 5 [ stop ]
+  forward :size
+  left 30 tree :size * 2 / 3
+  right 60 tree :size * 2 / 3
+  left 30 back :size
+end
+to spiral :size
+  if :size > 40 [ stop ]
+  forward :size right 90
+  spiral :size + 10
+end
+tree 30
+spiral 10
+";
+        let mut profiles = vec![];
+        for (engine, name) in [
+            (Engine::Bytecode, "profile"),
+            (Engine::TreeWalk, "profile_tree_walk"),
+        ] {
+            let options = Options {
+                engine,
+                ..Options::default()
+            };
+            let image_path = format!("img/{}.svg", name);
+            let (profile, result) = run_profiled(input, &image_path, &options);
+            result.unwrap();
+            profiles.push(profile);
+        }
+        assert_eq!(profiles[0].table(), profiles[1].table());
+        assert_eq!(profiles[0].folded(), profiles[1].folded());
+
+        let profile = &profiles[0];
+        // Recursive calls are counted once in the procedure they run under
+        let tree = profile.procedures["tree"];
+        assert_eq!((tree.calls, tree.primitives, tree.segments), (63, 155, 62));
+        let spiral = profile.procedures["spiral"];
+        assert_eq!(
+            spiral,
+            Counts {
+                calls: 5,
+                primitives: 8,
+                length: 100.0,
+                segments: 4,
+            }
+        );
+        assert_eq!(profile.lines[&3].primitives, 31);
+        assert_eq!(profile.lines[&4].calls, 31);
+        assert_eq!(profile.lines[&13].calls, 1);
+        assert_eq!(profile.lines[&13].primitives, 0);
+
+        let folded = profile.folded();
+        assert!(folded.contains("(top level);spiral 8\n"));
+        assert!(folded.contains("(top level);tree 5\n"));
+        assert!(folded.contains("(top level);tree;tree 10\n"));
+        assert!(profile
+            .table()
+            .contains("spiral                       5           8      100.00         4"));
+
+        // Inputs depending on the turtle run the instruction once per turtle
+        // in the bytecode, which still counts once
+        let input = "tell [0 1 2]\nforward who * 10 + 10 right 90\n";
+        let mut profiles = vec![];
+        for engine in [Engine::Bytecode, Engine::TreeWalk] {
+            let options = Options {
+                engine,
+                ..Options::default()
+            };
+            let (profile, result) = run_profiled(input, "img/profile_turtles.svg", &options);
+            result.unwrap();
+            profiles.push(profile);
+        }
+        assert_eq!(profiles[0].table(), profiles[1].table());
+        assert_eq!(profiles[0].folded(), profiles[1].folded());
+        let line = profiles[0].lines[&2];
+        assert_eq!((line.primitives, line.segments), (2, 3));
+        assert_eq!(line.length, 60.0);
+    }
+
+    #[test]
+    fn test_record() {
+        let input = "to square :side repeat 4 [ forward :side right 90 ] end
+            setpencolor \"blue square 100
+            penup back 50 pendown setpensize 3 arc 90 20
+            filled \"red [ forward 30 right 120 forward 30 right 120 forward 30 ]
+            tell [1 2] ask 2 [ right 90 label \"two ] hideturtle";
+        let mut recordings = vec![];
+        for engine in [Engine::Bytecode, Engine::TreeWalk] {
+            let options = Options {
+                engine,
+                ..Options::default()
+            };
+            let (recording, result) = record(input, &options);
+            result.unwrap();
+            recordings.push(recording);
+        }
+        assert_eq!(recordings[0], recordings[1]);
+        let recording = &recordings[0];
+
+        // A closed square of side 100, drawn clockwise from the origin
+        let near = |a: (f64, f64), b: (f64, f64)| (a.0 - b.0).hypot(a.1 - b.1) < 1e-9;
+        assert_eq!(recording.segments.len(), 4);
+        let paths = recording.paths(1e-9);
+        let square = &paths[0];
+        assert_eq!(square.len(), 5);
+        assert!(near(square[0], square[4]));
+        assert!(near(square[1], (0.0, 100.0)));
+        assert!(near(square[2], (100.0, 100.0)));
+        assert!(recording
+            .segments
+            .iter()
+            .all(|segment| (segment.length() - 100.0).abs() < 1e-9));
+        assert_eq!(
+            recording.segments[0].style,
+            Style {
+                color: String::from("blue"),
+                width: 1.0,
+            }
+        );
+
+        let arc = &recording.curves[0];
+        assert!(near(arc.center, (0.0, -50.0)));
+        assert!(near(arc.points()[0], (0.0, -30.0)));
+        assert!(near(*arc.points().last().unwrap(), (20.0, -50.0)));
+        assert_eq!(arc.style.width, 3.0);
+
+        // The filled triangle is closed back to its start
+        let Fill::Shape { points, color, .. } = &recording.fills[0] else {
+            panic!("Expected a filled shape");
+        };
+        assert_eq!(color, "red");
+        assert_eq!(points.len(), 4);
+        let triangle = &paths[1];
+        assert!(near(triangle[0], (0.0, -50.0)));
+        assert!(near(triangle[0], *triangle.last().unwrap()));
+
+        assert_eq!(recording.labels[0].text, "two");
+        assert_eq!(recording.labels[0].rotation, 90.0);
+        assert_eq!(recording.turtles.len(), 3);
+        let two = &recording.turtles[2];
+        assert_eq!((two.id, two.heading, two.visible), (2, 90.0, false));
+        assert!(recording.turtles[0].visible);
+    }
+
+    fn run_limited(input: &str, image_path: &str, limits: Limits) -> Result<(), LogoError> {
+        let options = Options {
+            limits,
+            ..Options::default()
+        };
+        run_with_options(input, image_path, &options)
+    }
+
+    #[test]
+    fn test_limits() {
+        let limits = Limits {
+            instructions: Some(1000),
+            ..Limits::default()
+        };
+        let result = run_limited(
+            "repeat 1e12 [ forward 1 turn 1 ]",
+            "img/limit_instructions.svg",
+            limits,
+        );
+        assert_eq!(
+            result,
+            Err(LogoError::LimitExceeded(Limit::Instructions(1000)))
+        );
+        // The partial drawing is still saved
+        let svg = std::fs::read_to_string("img/limit_instructions.svg").unwrap();
+        assert!(svg.contains("<path d=\"M0 0 L0 -1"));
+        // Entering a repeat and each further iteration count once in both engines
+        for engine in [Engine::Bytecode, Engine::TreeWalk] {
+            let options = |instructions| Options {
+                engine,
+                limits: Limits {
+                    instructions: Some(instructions),
+                    ..Limits::default()
+                },
+                ..Options::default()
+            };
+            assert!(execute("repeat 3 [ ]", &options(3)).is_ok());
+            assert_eq!(
+                execute("repeat 3 [ ]", &options(2)),
+                Err(LogoError::LimitExceeded(Limit::Instructions(2)))
+            );
+        }
+
+        let limits = Limits {
+            depth: Some(50),
+            ..Limits::default()
+        };
+        let result = run_limited("to f f forward 1 end f", "img/limit_depth.svg", limits);
+        assert_eq!(result, Err(LogoError::LimitExceeded(Limit::Depth(50))));
+
+        let limits = Limits {
+            segments: Some(10),
+            ..Limits::default()
+        };
+        let result = run_limited(
+            "repeat 100 [ forward 1 ]",
+            "img/limit_segments.svg",
+            limits.clone(),
+        );
+        assert_eq!(result, Err(LogoError::LimitExceeded(Limit::Segments(10))));
+        // A single move wrapping around the edges many times
+        let result = run_limited("forward 1e12", "img/limit_wrapping.svg", limits);
+        assert_eq!(result, Err(LogoError::LimitExceeded(Limit::Segments(10))));
+
+        let limits = Limits {
+            time: Some(Duration::from_millis(50)),
+            ..Limits::default()
+        };
+        let result = run_limited("repeat 1e12 [ ]", "img/limit_time.svg", limits.clone());
+        assert_eq!(
+            result,
+            Err(LogoError::LimitExceeded(Limit::Time(
+                Duration::from_millis(50)
+            )))
+        );
+        // A single move wrapping around the edges without drawing. Where the
+        // turtle stops depends on the timing, so the image is left untracked.
+        let result = run_limited(
+            "penup right 1 forward 1e13",
+            "target/limit_time_wrapping.svg",
+            limits,
+        );
+        assert_eq!(
+            result,
+            Err(LogoError::LimitExceeded(Limit::Time(
+                Duration::from_millis(50)
+            )))
+        );
+    }
+
+    #[test]
+    fn test_deep_recursion() {
+        // Far deeper than the native stack of a test thread would allow
+        let input = "to down :n if :n > 0 [ forward 0.001 down :n - 1 ] end down 100000";
+        run(input, "img/deep_recursion.svg").unwrap();
+
+        let limits = Limits {
+            depth: Some(500000),
+            ..Limits::default()
+        };
+        let result = run_limited(
+            "to f f forward 1 end f",
+            "img/deep_recursion_limit.svg",
+            limits,
+        );
+        assert_eq!(result, Err(LogoError::LimitExceeded(Limit::Depth(500000))));
+    }
+
+    #[test]
+    fn test_deep_nesting() {
+        let nested = |depth| "repeat 1 [ ".repeat(depth) + "forward 1" + &" ]".repeat(depth);
+        let long = |operators| String::from("forward 1") + &" + 1".repeat(operators);
+
+        // The deepest programs accepted can still be walked on a test thread
+        for input in [nested(100), long(100)] {
+            for engine in [Engine::Bytecode, Engine::TreeWalk] {
+                let options = Options {
+                    engine,
+                    ..Options::default()
+                };
+                execute(&input, &options).unwrap();
+            }
+            format(&input).unwrap();
+            assert!(check(&input).is_empty());
+        }
+
+        for input in [nested(101), nested(20000), long(101), long(20000)] {
+            match execute(&input, &Options::default()) {
+                Err(LogoError::Parse(error)) => {
+                    assert_eq!(error.message, "Nested more than 100 deep")
+                }
+                other => panic!("Expected a parse error, got {:?}", other),
+            }
+        }
+
+        // Each item nests its index one level deeper
+        let items = |depth| {
+            let index = "item ".repeat(depth) + "1" + &" :l".repeat(depth);
+            format!("to f [:l] forward {} end (f 1)", index)
+        };
+        execute(&items(100), &Options::default()).unwrap();
+        let error = format(&items(101)).unwrap_err();
+        assert_eq!(error.message, "Nested more than 100 deep");
+    }
+
+    #[test]
+    fn test_tail_calls() {
+        // Tail calls do not nest, so only the instruction limit stops the spiral
+        let limits = Limits {
+            instructions: Some(10000),
+            depth: Some(5),
+            ..Limits::default()
+        };
+        let result = run_limited(
+            "to spiral :n forward :n turn 90 spiral :n + 2 end spiral 1",
+            "img/tail_spiral.svg",
+            limits.clone(),
+        );
+        assert_eq!(
+            result,
+            Err(LogoError::LimitExceeded(Limit::Instructions(10000)))
+        );
+
+        let input = "to spiral :n if :n > 200 [ stop ] forward :n turn 90 spiral :n + 2 end
+            to zigzag :n if :n > 0 [ forward 10 turn 90 zigzag :n - 1 ] end
+            spiral 1 zigzag 20";
+        run_limited(input, "img/tail_guarded.svg", limits.clone()).unwrap();
+
+        // Comments after the call leave it in tail position
+        let input = "to spiral :n if :n > 200 [ stop ] forward :n turn 90
+            spiral :n + 2 ; keep going
+            end
+            to zigzag :n if :n > 0 [ forward 10 turn 90 zigzag :n - 1 ; again
+            ] end
+            spiral 1 zigzag 20";
+        for engine in [Engine::Bytecode, Engine::TreeWalk] {
+            let options = Options {
+                engine,
+                limits: limits.clone(),
+                ..Options::default()
+            };
+            execute(input, &options).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_forward_references() {
+        // Called before being defined, and calling each other
+        let input = "label [ go to ] steps 6
+            to steps :n if :n > 0 [ forward 20 turn 90 stairs :n - 1 ] end
+            to stairs :n if :n > 0 [ forward 10 turn -90 steps :n - 1 ] end";
+        run(input, "img/forward_references.svg").unwrap();
+
+        let input = "to stairs :n if :n > 0 [ forward 10 turn -90 steps :n - 1 ] end
+            to steps :n if :n > 0 [ forward 20 turn 90 stairs :n - 1 ] end
+            label [ go to ] steps 6";
+        run(input, "img/forward_references_ordered.svg").unwrap();
+
+        let svg = std::fs::read_to_string("img/forward_references.svg").unwrap();
+        let ordered = std::fs::read_to_string("img/forward_references_ordered.svg").unwrap();
+        assert_eq!(svg, ordered);
+        assert!(svg.contains("d=\"M0 0 L0 -20 L10 -20 L10 -40"));
+        assert!(svg.contains("go to"));
+
+        // A list holding `end` does not end the definition
+        let input = "to a label [the end] end\nto b fd 10 end\na b";
+        run(input, "img/forward_references_list.svg").unwrap();
+        assert!(check(input).is_empty());
+    }
+
+    #[test]
+    fn test_animated_star() {
+        let input = "repeat 5 [ forward 100 turn 144 ]";
+        let image_path = "img/star_animated.svg";
+
+        let options = Options {
+            animation: Some(Animation {
+                speed: 100.0,
+                sprite: true,
+            }),
+            ..Options::default()
+        };
+        run_with_options(input, image_path, &options).unwrap();
+        let svg = std::fs::read_to_string(image_path).unwrap();
+        assert!(svg.contains("attributeName=\"stroke-dashoffset\""));
+        assert!(svg.contains("<animateMotion"));
+        assert!(svg.contains("dur=\"5s\""));
     }
 }
@@ -0,0 +1,169 @@
+//! Drives the language server over stdio the way an editor does
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Child, ChildStdout, Command, Stdio};
+
+use serde_json::{json, Value};
+
+struct Client {
+    server: Child,
+    output: BufReader<ChildStdout>,
+    next_id: u64,
+}
+
+impl Client {
+    fn start() -> Self {
+        let mut server = Command::new(env!("CARGO_BIN_EXE_logo_lsp"))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Unable to start the language server");
+        let output = BufReader::new(server.stdout.take().unwrap());
+        Self {
+            server,
+            output,
+            next_id: 0,
+        }
+    }
+
+    fn send(&mut self, message: Value) {
+        let body = message.to_string();
+        let input = self.server.stdin.as_mut().unwrap();
+        write!(input, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        input.flush().unwrap();
+    }
+
+    fn receive(&mut self) -> Value {
+        let mut length = 0;
+        loop {
+            let mut header = String::new();
+            self.output.read_line(&mut header).unwrap();
+            match header.trim().strip_prefix("Content-Length: ") {
+                Some(value) => length = value.parse().unwrap(),
+                None if header.trim().is_empty() => break,
+                None => {}
+            }
+        }
+        let mut body = vec![0; length];
+        self.output.read_exact(&mut body).unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    fn request(&mut self, method: &str, params: Value) -> Value {
+        self.next_id += 1;
+        let id = self.next_id;
+        self.send(json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params}));
+        loop {
+            let message = self.receive();
+            if message["id"] == id {
+                return message["result"].clone();
+            }
+        }
+    }
+
+    fn notify(&mut self, method: &str, params: Value) {
+        self.send(json!({"jsonrpc": "2.0", "method": method, "params": params}));
+    }
+
+    /// Diagnostics published for the last change
+    fn diagnostics(&mut self) -> Value {
+        loop {
+            let message = self.receive();
+            if message["method"] == "textDocument/publishDiagnostics" {
+                return message["params"]["diagnostics"].clone();
+            }
+        }
+    }
+}
+
+const URI: &str = "file:///shapes.logo";
+
+const PROGRAM: &str = "to square :side
+  repeat 4 [ forward :side right 90 ]
+end
+to star [:size 100] ; five points
+  repeat 5 [ forward :size right 144 ]
+end
+square 50
+triangle 10
+";
+
+fn at(line: u32, character: u32) -> Value {
+    json!({"textDocument": {"uri": URI}, "position": {"line": line, "character": character}})
+}
+
+fn range(start: (u32, u32), end: (u32, u32)) -> Value {
+    json!({
+        "start": {"line": start.0, "character": start.1},
+        "end": {"line": end.0, "character": end.1},
+    })
+}
+
+#[test]
+fn test_language_server() {
+    let mut client = Client::start();
+    let initialized = client.request("initialize", json!({"capabilities": {}}));
+    assert_eq!(initialized["capabilities"]["definitionProvider"], true);
+    client.notify("initialized", json!({}));
+
+    client.notify(
+        "textDocument/didOpen",
+        json!({"textDocument": {"uri": URI, "languageId": "logo", "version": 1, "text": PROGRAM}}),
+    );
+    let diagnostics = client.diagnostics();
+    assert_eq!(diagnostics.as_array().unwrap().len(), 1);
+    assert_eq!(
+        diagnostics[0]["message"],
+        "Function 'triangle' is not defined"
+    );
+    assert_eq!(diagnostics[0]["severity"], 1);
+    assert_eq!(diagnostics[0]["range"], range((7, 0), (7, 8)));
+
+    let definition = client.request("textDocument/definition", at(6, 3));
+    assert_eq!(definition["uri"], URI);
+    assert_eq!(definition["range"], range((0, 0), (0, 15)));
+
+    let hover = client.request("textDocument/hover", at(6, 0));
+    assert_eq!(hover["contents"]["value"], "```logo\nto square :side\n```");
+    assert_eq!(client.request("textDocument/hover", at(6, 8)), Value::Null);
+
+    let completion = client.request("textDocument/completion", at(7, 0));
+    let labels: Vec<_> = completion
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|item| item["label"].clone())
+        .collect();
+    assert!(labels.contains(&json!("forward")));
+    assert!(labels.contains(&json!("setpencolor")));
+    let star = completion
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|item| item["label"] == "star")
+        .unwrap();
+    assert_eq!(star["detail"], "to star [:size 100]");
+
+    let symbols = client.request(
+        "textDocument/documentSymbol",
+        json!({"textDocument": {"uri": URI}}),
+    );
+    assert_eq!(symbols[0]["name"], "square");
+    assert_eq!(symbols[0]["range"], range((0, 0), (2, 3)));
+    assert_eq!(symbols[0]["selectionRange"], range((0, 3), (0, 9)));
+    assert_eq!(symbols[1]["name"], "star");
+    assert_eq!(symbols[1]["range"], range((3, 0), (5, 3)));
+
+    // Fixing the program clears the error
+    client.notify(
+        "textDocument/didChange",
+        json!({
+            "textDocument": {"uri": URI, "version": 2},
+            "contentChanges": [{"text": PROGRAM.replace("triangle 10", "star")}],
+        }),
+    );
+    assert_eq!(client.diagnostics(), json!([]));
+
+    client.request("shutdown", Value::Null);
+    client.notify("exit", Value::Null);
+    assert!(client.server.wait().unwrap().success());
+}
@@ -0,0 +1,103 @@
+//! Runs what the fuzz targets run on variations of the example programs, so
+//! that stable builds catch panics without cargo-fuzz. Inputs the fuzzers
+//! found go in `FOUND`, or `nested` when too long to write out.
+use std::fs;
+use std::panic;
+use std::path::Path;
+use std::time::Duration;
+
+use logo_interpreter::{check, format, parse, record, render, Engine, Limits, Options};
+
+/// Limits the fuzz target runs programs under
+const BOUNDED: Limits = Limits {
+    instructions: Some(10_000),
+    depth: Some(100),
+    segments: Some(10_000),
+    time: Some(Duration::from_secs(1)),
+};
+
+/// Only the time limit, which has to stop a single move as well
+const TIMED: Limits = Limits {
+    instructions: None,
+    depth: None,
+    segments: None,
+    time: Some(Duration::from_millis(100)),
+};
+
+/// Inputs that used to panic or never finish, with the limits they ran under
+const FOUND: [(&str, Limits); 7] = [
+    ("forward 1/0", BOUNDED),
+    ("fd 1e20", BOUNDED),
+    ("filled \"red [ forward 1e20 ]", BOUNDED),
+    ("arc 1e12 10", BOUNDED),
+    ("tell [1e300] newturtle forward 10", BOUNDED),
+    ("to f [:l] forward item 0 - 1e300 :l end (f 1 2)", BOUNDED),
+    ("penup right 1 forward 1e13", TIMED),
+];
+
+/// Brackets and expressions around the deepest nesting allowed, far deeper
+/// ones having overflowed the stack
+fn nested() -> Vec<String> {
+    let mut inputs = vec![];
+    for depth in [100, 101, 1000, 20000] {
+        inputs.push("repeat 1 [ ".repeat(depth) + "forward 1" + &" ]".repeat(depth));
+        inputs.push(String::from("forward 1") + &" + 1".repeat(depth));
+    }
+    inputs
+}
+
+/// The inputs found, then every prefix of the programs and the programs
+/// without each of their words
+fn inputs() -> Vec<(String, Limits)> {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples");
+    let mut inputs: Vec<(String, Limits)> = FOUND
+        .iter()
+        .map(|(input, limits)| (input.to_string(), limits.clone()))
+        .collect();
+    inputs.extend(nested().into_iter().map(|input| (input, BOUNDED)));
+    for entry in fs::read_dir(directory).unwrap() {
+        let program = fs::read_to_string(entry.unwrap().path()).unwrap();
+        let prefixes = program
+            .char_indices()
+            .map(|(i, _)| program[..i].to_string());
+        inputs.extend(prefixes.map(|input| (input, BOUNDED)));
+        let words: Vec<&str> = program.split_whitespace().collect();
+        for skipped in 0..words.len() {
+            let mut kept = words.clone();
+            kept.remove(skipped);
+            inputs.push((kept.join(" "), BOUNDED));
+        }
+    }
+    inputs
+}
+
+#[test]
+fn test_no_panics() {
+    let mut failures = vec![];
+    for (input, limits) in inputs() {
+        let parsed = panic::catch_unwind(|| {
+            let _ = parse(&input);
+            let _ = check(&input);
+            let _ = format(&input);
+        });
+        if parsed.is_err() {
+            failures.push(format!("parsing {:?}", input));
+        }
+
+        for engine in [Engine::Bytecode, Engine::TreeWalk] {
+            let options = Options {
+                engine,
+                limits: limits.clone(),
+                ..Options::default()
+            };
+            let executed = panic::catch_unwind(|| {
+                let _ = render(&input, &options);
+                let _ = record(&input, &options);
+            });
+            if executed.is_err() {
+                failures.push(format!("running {:?} ({:?})", input, engine));
+            }
+        }
+    }
+    assert!(failures.is_empty(), "Panicked {}", failures.join("\n"));
+}
@@ -0,0 +1,260 @@
+//! Renders the example programs and compares the drawings with the snapshots
+//! checked in under `tests/snapshots`. After an intended change to the
+//! drawings, rewrite the snapshots with
+//! `UPDATE_SNAPSHOTS=1 cargo test --test snapshots` and review their diff.
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use logo_interpreter::{render, Engine, Options};
+
+/// Digits after the decimal point written for coordinates
+const PRECISION: usize = 3;
+
+/// Largest difference between a number of the snapshot and of the drawing,
+/// allowing for rounding to land on the next digit
+const TOLERANCE: f64 = 0.002;
+
+/// Differing lines shown for each side of a failed comparison
+const SHOWN: usize = 20;
+
+/// Drawing of the program as SVG with one path command per line, which stays
+/// a valid image and gives diffs by segment
+fn canonical(svg: &str) -> String {
+    let mut canonical = String::new();
+    for line in svg.lines() {
+        match line.find(" d=\"") {
+            Some(start) => {
+                let start = start + 4;
+                let end = start + line[start..].find('"').unwrap_or(0);
+                let mut data = String::new();
+                for part in line[start..end].split(' ') {
+                    // Arguments of a command stay on its line
+                    if !data.is_empty() {
+                        data.push(if part.starts_with(char::is_alphabetic) {
+                            '\n'
+                        } else {
+                            ' '
+                        });
+                    }
+                    data.push_str(part);
+                }
+                let _ = writeln!(canonical, "{}{}{}", &line[..start], data, &line[end..]);
+            }
+            None => {
+                let _ = writeln!(canonical, "{}", line);
+            }
+        }
+    }
+    canonical
+}
+
+#[derive(Debug, PartialEq)]
+enum Part<'a> {
+    Text(&'a str),
+    Number(f64),
+}
+
+/// Splits a line into numbers and the text between them
+fn parts(line: &str) -> Vec<Part<'_>> {
+    let bytes = line.as_bytes();
+    let starts_number = |i: usize| {
+        let digit_at = |i: usize| bytes.get(i).is_some_and(u8::is_ascii_digit);
+        // Letter of a path command, such as the `L` of `L100`, standing alone
+        // before its first number
+        let command = |i: usize| {
+            b"MmLlHhVvCcSsQqTtAaZz".contains(&bytes[i])
+                && (i == 0 || !(bytes[i - 1].is_ascii_alphanumeric() || bytes[i - 1] == b'#'))
+        };
+        let after_word = i > 0 && bytes[i - 1].is_ascii_alphanumeric() && !command(i - 1);
+        !after_word
+            && (digit_at(i)
+                || (bytes[i] == b'-' && (digit_at(i + 1) || bytes.get(i + 1) == Some(&b'.')))
+                || (bytes[i] == b'.' && digit_at(i + 1)))
+    };
+
+    let mut parts = vec![];
+    let mut text = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        if !starts_number(i) {
+            i += 1;
+            continue;
+        }
+        let end = i
+            + 1
+            + bytes[i + 1..]
+                .iter()
+                .take_while(|&&byte| byte.is_ascii_digit() || byte == b'.')
+                .count();
+        if let Ok(number) = line[i..end].parse() {
+            if text < i {
+                parts.push(Part::Text(&line[text..i]));
+            }
+            parts.push(Part::Number(number));
+            text = end;
+        }
+        i = end;
+    }
+    if text < line.len() {
+        parts.push(Part::Text(&line[text..]));
+    }
+    parts
+}
+
+/// Whether two lines hold the same text and numbers within the tolerance
+fn matches(expected: &str, actual: &str) -> bool {
+    let (expected, actual) = (parts(expected), parts(actual));
+    expected.len() == actual.len()
+        && expected.iter().zip(&actual).all(|parts| match parts {
+            (Part::Number(a), Part::Number(b)) => (a - b).abs() <= TOLERANCE,
+            (a, b) => a == b,
+        })
+}
+
+/// Lines that differ between the snapshot and the drawing, between the
+/// lines they start and end with in common. `None` when they match.
+fn diff(expected: &str, actual: &str) -> Option<String> {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+    let prefix = expected
+        .iter()
+        .zip(&actual)
+        .take_while(|(a, b)| matches(a, b))
+        .count();
+    if prefix == expected.len() && prefix == actual.len() {
+        return None;
+    }
+    let suffix = expected[prefix..]
+        .iter()
+        .rev()
+        .zip(actual[prefix..].iter().rev())
+        .take_while(|(a, b)| matches(a, b))
+        .count();
+
+    let mut diff = String::new();
+    let mut side = |sign: char, lines: &[&str]| {
+        let changed = &lines[prefix..lines.len() - suffix];
+        for (i, line) in changed.iter().enumerate().take(SHOWN) {
+            let _ = writeln!(diff, "{:>6} {} {}", prefix + i + 1, sign, line);
+        }
+        if changed.len() > SHOWN {
+            let _ = writeln!(
+                diff,
+                "       {} ... {} more lines",
+                sign,
+                changed.len() - SHOWN
+            );
+        }
+    };
+    side('-', &expected);
+    side('+', &actual);
+    Some(diff)
+}
+
+fn examples() -> Vec<PathBuf> {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples");
+    let mut examples: Vec<PathBuf> = fs::read_dir(directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "logo")
+        })
+        .collect();
+    examples.sort();
+    examples
+}
+
+#[test]
+fn test_snapshots() {
+    let update = std::env::var_os("UPDATE_SNAPSHOTS").is_some_and(|value| value != "0");
+    let snapshots = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots");
+    let mut failures = vec![];
+
+    for example in examples() {
+        let input = fs::read_to_string(&example).unwrap();
+        let name = example.file_stem().unwrap().to_string_lossy();
+        let snapshot_path = snapshots.join(format!("{}.svg", name));
+
+        for engine in [Engine::Bytecode, Engine::TreeWalk] {
+            let options = Options {
+                precision: PRECISION,
+                engine,
+                ..Options::default()
+            };
+            let (svg, result) = render(&input, &options);
+            if let Err(error) = result {
+                failures.push(format!("{} ({:?}) failed: {}", name, engine, error));
+                continue;
+            }
+            let drawing = canonical(&svg);
+
+            if update && engine == Engine::Bytecode {
+                fs::write(&snapshot_path, &drawing).unwrap();
+                continue;
+            }
+            let Ok(snapshot) = fs::read_to_string(&snapshot_path) else {
+                failures.push(format!("{} has no snapshot", name));
+                break;
+            };
+            if let Some(diff) = diff(&snapshot, &drawing) {
+                failures.push(format!(
+                    "{} ({:?}) differs from {}:\n{}",
+                    name,
+                    engine,
+                    snapshot_path.display(),
+                    diff
+                ));
+            }
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{}\nRun with UPDATE_SNAPSHOTS=1 to accept the drawings as they are now",
+        failures.join("\n")
+    );
+}
+
+#[test]
+fn test_snapshot_comparison() {
+    let snapshot = "<path d=\"M0 0\nL100 -50.001\" stroke-width=\"1\"/>\n<circle/>\n";
+    assert_eq!(diff(snapshot, snapshot), None);
+    // Rounding differences are tolerated
+    assert_eq!(
+        diff(snapshot, &snapshot.replace("-50.001", "-50.002")),
+        None
+    );
+
+    let moved = snapshot.replace("L100", "L101");
+    assert_eq!(
+        diff(snapshot, &moved).unwrap(),
+        "     2 - L100 -50.001\" stroke-width=\"1\"/>\n     2 + L101 -50.001\" stroke-width=\"1\"/>\n"
+    );
+    // The first coordinate of a command is a number too
+    let nudged = snapshot.replace("L100", "L100.001");
+    assert_eq!(diff(snapshot, &nudged), None);
+    assert_eq!(
+        parts("M-5.2 0\nA5 5"),
+        [
+            Part::Text("M"),
+            Part::Number(-5.2),
+            Part::Text(" "),
+            Part::Number(0.0),
+            Part::Text("\nA"),
+            Part::Number(5.0),
+            Part::Text(" "),
+            Part::Number(5.0)
+        ]
+    );
+    let restyled = snapshot.replace("stroke-width=\"1\"", "stroke=\"red\" stroke-width=\"1\"");
+    assert!(diff(snapshot, &restyled).is_some());
+    let longer = snapshot.to_string() + "<circle/>\n";
+    assert_eq!(diff(snapshot, &longer).unwrap(), "     4 + <circle/>\n");
+
+    assert_eq!(
+        canonical("<path d=\"M0 0 L10 0 A5 5 0 0 1 0 0 Z\" fill=\"none\"/>"),
+        "<path d=\"M0 0\nL10 0\nA5 5 0 0 1 0 0\nZ\" fill=\"none\"/>\n"
+    );
+}